@@ -0,0 +1,41 @@
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Files up to this size are read into memory once and shared by hashing,
+/// validation, date extraction and the copy. Larger files are streamed.
+pub const MAX_BUFFERED_BYTES: u64 = 32 * 1024 * 1024;
+
+thread_local! {
+    // One reusable buffer per worker thread, so steady-state imports do not
+    // allocate per file
+    static BUFFER: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Run `f` with the whole contents of `path`, or with `None` when the file is
+/// too large to buffer or cannot be read. Callers fall back to streaming from
+/// disk in that case, which reports any read error in context.
+pub fn with_contents<R>(path: &Path, f: impl FnOnce(Option<&[u8]>) -> R) -> R {
+    BUFFER.with(|cell| {
+        let mut buffer = cell.borrow_mut();
+        buffer.clear();
+        let loaded = read_whole(path, &mut buffer).is_ok();
+        f(loaded.then_some(buffer.as_slice()))
+    })
+}
+
+fn read_whole(path: &Path, buffer: &mut Vec<u8>) -> std::io::Result<()> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len > MAX_BUFFERED_BYTES {
+        return Err(std::io::Error::other("file too large to buffer"));
+    }
+    buffer.reserve(len as usize);
+    // The file may grow between stat and read; never exceed the limit
+    file.take(MAX_BUFFERED_BYTES + 1).read_to_end(buffer)?;
+    if buffer.len() as u64 > MAX_BUFFERED_BYTES {
+        return Err(std::io::Error::other("file too large to buffer"));
+    }
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use crate::metadata::{Digests, HashAlgorithm, parse_hash};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+const CACHE_FILE: &str = ".hash-cache.json";
+
+/// Persistent map from source file to its content hashes, so re-importing an
+/// unchanged source does not re-read every byte. Entries are keyed by
+/// absolute path and only trusted while size, mtime and inode still match.
+#[derive(Serialize, Deserialize)]
+pub struct HashCache {
+    version: u8,
+    entries: BTreeMap<String, CachedHash>,
+    #[serde(skip)]
+    dirty: bool,
+}
+
+/// Size, mtime and inode of a source file. Taken before a file is read and
+/// compared after, it shows whether the bytes read belong to the file as it
+/// is now.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Fingerprint {
+    size: u64,
+    mtime_ns: u64,
+    inode: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedHash {
+    #[serde(flatten)]
+    fingerprint: Fingerprint,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    blake3: Option<String>,
+}
+
+impl Default for HashCache {
+    fn default() -> Self {
+        HashCache {
+            version: 1,
+            entries: BTreeMap::new(),
+            dirty: false,
+        }
+    }
+}
+
+impl HashCache {
+    /// Cached digests for `path`, whose current fingerprint is
+    /// `fingerprint`, only if every requested algorithm is known.
+    pub fn lookup(&self, path: &Path, fingerprint: Fingerprint, algorithms: &[HashAlgorithm]) -> Option<Digests> {
+        let cached = self.entries.get(&cache_key(path)?)?;
+        if fingerprint != cached.fingerprint {
+            return None;
+        }
+        let mut digests = Digests::default();
+        for &algorithm in algorithms {
+            let hex = match algorithm {
+                HashAlgorithm::Sha256 => cached.sha256.as_deref(),
+                HashAlgorithm::Blake3 => cached.blake3.as_deref(),
+            };
+            digests.set(algorithm, parse_hash(hex?)?);
+        }
+        Some(digests)
+    }
+
+    /// Record the digest of bytes read from `path` while it had `fingerprint`.
+    /// Nothing is recorded if the file has changed since.
+    pub fn record(&mut self, path: &Path, fingerprint: Fingerprint, algorithm: HashAlgorithm, hex: &str) {
+        let Some(key) = cache_key(path) else {
+            return;
+        };
+        if self::fingerprint(path) != Some(fingerprint) {
+            return;
+        }
+        let entry = self.entries.entry(key).or_insert_with(|| CachedHash::new(fingerprint));
+        // A changed file invalidates digests recorded under the old fingerprint
+        if entry.fingerprint != fingerprint {
+            *entry = CachedHash::new(fingerprint);
+        }
+        let hex = Some(hex.to_string());
+        match algorithm {
+            HashAlgorithm::Sha256 => entry.sha256 = hex,
+            HashAlgorithm::Blake3 => entry.blake3 = hex,
+        }
+        self.dirty = true;
+    }
+}
+
+impl CachedHash {
+    fn new(fingerprint: Fingerprint) -> Self {
+        CachedHash {
+            fingerprint,
+            sha256: None,
+            blake3: None,
+        }
+    }
+}
+
+pub fn cache_path(target: &Path) -> PathBuf {
+    target.join(CACHE_FILE)
+}
+
+/// Read the cache in `target`; a missing one reads as empty. A corrupt one
+/// also reads as empty, and is reported through `warn`.
+pub fn load_cache(target: &Path, warn: &dyn Fn(&str)) -> HashCache {
+    let path = cache_path(target);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(c) => c,
+        Err(_) => return HashCache::default(),
+    };
+    match serde_json::from_str(&content) {
+        Ok(cache) => cache,
+        Err(_) => {
+            warn(&format!("Corrupt hash cache at {}, starting fresh", path.display()));
+            HashCache::default()
+        }
+    }
+}
+
+/// Write the cache if anything was recorded. Written to a temporary file and
+/// renamed, so an interrupted save never leaves a truncated cache behind.
+pub fn save_cache(target: &Path, cache: &HashCache) -> std::io::Result<()> {
+    if !cache.dirty {
+        return Ok(());
+    }
+    std::fs::create_dir_all(target)?;
+    let path = cache_path(target);
+    let tmp = path.with_extension("json.tmp");
+    let json = serde_json::to_string(cache).map_err(std::io::Error::other)?;
+    std::fs::write(&tmp, json)?;
+    std::fs::rename(&tmp, &path)
+}
+
+/// Remove the cache file. Returns whether there was one.
+pub fn clear_cache(target: &Path) -> std::io::Result<bool> {
+    match std::fs::remove_file(cache_path(target)) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+fn cache_key(path: &Path) -> Option<String> {
+    std::path::absolute(path)
+        .ok()
+        .map(|p| p.to_string_lossy().into_owned())
+}
+
+pub fn fingerprint(path: &Path) -> Option<Fingerprint> {
+    let metadata = std::fs::metadata(path).ok()?;
+    let mtime_ns = metadata
+        .modified()
+        .ok()?
+        .duration_since(std::time::UNIX_EPOCH)
+        .ok()?
+        .as_nanos() as u64;
+    #[cfg(unix)]
+    let inode = std::os::unix::fs::MetadataExt::ino(&metadata);
+    #[cfg(not(unix))]
+    let inode = 0;
+    Some(Fingerprint {
+        size: metadata.len(),
+        mtime_ns,
+        inode,
+    })
+}
@@ -0,0 +1,112 @@
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Condvar, Mutex};
+
+/// Worker count for a class of I/O: a fixed number, or `auto` to pick one
+/// from the storage the work touches.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Jobs {
+    Auto,
+    Fixed(NonZeroUsize),
+}
+
+impl FromStr for Jobs {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("auto") {
+            return Ok(Jobs::Auto);
+        }
+        s.parse::<NonZeroUsize>()
+            .map(Jobs::Fixed)
+            .map_err(|_| format!("expected a positive number or `auto`, got `{}`", s))
+    }
+}
+
+impl Jobs {
+    /// Resolve to a worker count. `auto` serialises work on rotational
+    /// devices, where parallel random reads or writes only add seeks, and
+    /// otherwise uses `parallel`.
+    pub fn resolve(self, path: &Path, parallel: usize) -> usize {
+        match self {
+            Jobs::Fixed(n) => n.get(),
+            Jobs::Auto if is_rotational(path) == Some(true) => 1,
+            Jobs::Auto => parallel.max(1),
+        }
+    }
+}
+
+/// Whether the block device holding `path` (or its nearest existing
+/// ancestor) is rotational, as reported by `/sys/dev/block`. `None` when it
+/// cannot be determined, e.g. on network filesystems or non-Linux systems.
+pub fn is_rotational(path: &Path) -> Option<bool> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::fs::MetadataExt;
+        let metadata = path.ancestors().find_map(|p| std::fs::metadata(p).ok())?;
+        let (major, minor) = device_numbers(metadata.dev());
+        rotational_flag(Path::new("/sys/dev/block"), major, minor)
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        None
+    }
+}
+
+/// Split a Linux `dev_t` into its major and minor numbers, as glibc's
+/// `major()` and `minor()` do.
+pub fn device_numbers(dev: u64) -> (u64, u64) {
+    let major = ((dev >> 8) & 0xfff) | ((dev >> 32) & 0xffff_f000);
+    let minor = (dev & 0xff) | ((dev >> 12) & 0xffff_ff00);
+    (major, minor)
+}
+
+/// Read the `queue/rotational` flag of device `major:minor` under
+/// `sys_dev_block`, normally `/sys/dev/block`, whose entries link to the
+/// device directories.
+pub fn rotational_flag(sys_dev_block: &Path, major: u64, minor: u64) -> Option<bool> {
+    let device = std::fs::canonicalize(sys_dev_block.join(format!("{}:{}", major, minor))).ok()?;
+    // Partitions have no queue of their own; their parent disk does
+    let flag = [device.join("queue/rotational"), device.join("../queue/rotational")]
+        .iter()
+        .find_map(|p| std::fs::read_to_string(p).ok())?;
+    Some(flag.trim() == "1")
+}
+
+/// Counting semaphore bounding how many copies write to the target at once.
+pub struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+pub struct Permit<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Semaphore {
+    pub fn new(permits: usize) -> Self {
+        Semaphore {
+            available: Mutex::new(permits.max(1)),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until a permit is free. It is returned when the guard drops.
+    pub fn acquire(&self) -> Permit<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        Permit { semaphore: self }
+    }
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
@@ -0,0 +1,314 @@
+use crate::import::DuplicatePolicy;
+use crate::metadata::{BuiltinExtractor, DateExtracted};
+use crate::scan;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+/// Project config, read from the target library.
+pub const PROJECT_CONFIG: &str = ".image-organiser.toml";
+
+/// Settings for an import, merged from the user config, the target's
+/// project config and the command line, later ones taking precedence.
+/// Ignore patterns accumulate instead.
+#[derive(Debug, Clone, Serialize)]
+pub struct Config {
+    /// Directory under the target for dated files
+    pub layout: Template,
+    /// File name for dated files, without extension
+    pub naming: Template,
+    /// Extensions to import (lowercase, no dot); others are skipped
+    pub extensions: Vec<String>,
+    /// Date extractors in priority order
+    pub date_sources: Vec<BuiltinExtractor>,
+    pub duplicates: DuplicatePolicy,
+    /// Gitignore-style patterns, relative to the source, for files to leave out
+    pub ignore: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            layout: Template("{year}/{month}".to_string()),
+            naming: Template("{year}{month}{day}_{hour}{minute}{second}".to_string()),
+            extensions: scan::DEFAULT_EXTENSIONS.iter().map(|e| e.to_string()).collect(),
+            date_sources: BuiltinExtractor::ALL.to_vec(),
+            duplicates: DuplicatePolicy::Copy,
+            ignore: Vec::new(),
+        }
+    }
+}
+
+/// One config file. Every key is optional.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    layout: Option<Template>,
+    naming: Option<Template>,
+    extensions: Option<Vec<String>>,
+    date_sources: Option<Vec<BuiltinExtractor>>,
+    duplicates: Option<DuplicatePolicy>,
+    #[serde(default)]
+    ignore: Vec<String>,
+}
+
+/// A config file that could not be read or is invalid.
+#[derive(Debug)]
+pub struct ConfigError {
+    pub path: PathBuf,
+    pub message: String,
+}
+
+impl std::fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// `$XDG_CONFIG_HOME/image-organiser/config.toml`, falling back to
+/// `~/.config` when `XDG_CONFIG_HOME` is unset.
+pub fn user_config_path() -> Option<PathBuf> {
+    let base = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+    Some(base.join("image-organiser").join("config.toml"))
+}
+
+pub fn project_config_path(target: &Path) -> PathBuf {
+    target.join(PROJECT_CONFIG)
+}
+
+impl Config {
+    /// Merge the user config and, given a target, its project config over
+    /// the defaults. Returns the config and the files that were read.
+    pub fn load(target: Option<&Path>) -> Result<(Config, Vec<PathBuf>), ConfigError> {
+        let mut config = Config::default();
+        let mut read = Vec::new();
+        let paths = user_config_path()
+            .into_iter()
+            .chain(target.map(project_config_path));
+        for path in paths {
+            let content = match std::fs::read_to_string(&path) {
+                Ok(content) => content,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+                Err(e) => {
+                    return Err(ConfigError {
+                        path,
+                        message: e.to_string(),
+                    });
+                }
+            };
+            let file: ConfigFile = toml::from_str(&content).map_err(|e| ConfigError {
+                path: path.clone(),
+                message: e.to_string(),
+            })?;
+            config.apply(file).map_err(|message| ConfigError {
+                path: path.clone(),
+                message,
+            })?;
+            read.push(path);
+        }
+        Ok((config, read))
+    }
+
+    fn apply(&mut self, file: ConfigFile) -> Result<(), String> {
+        if let Some(layout) = file.layout {
+            layout.check_layout()?;
+            self.layout = layout;
+        }
+        if let Some(naming) = file.naming {
+            naming.check_naming()?;
+            self.naming = naming;
+        }
+        if let Some(extensions) = file.extensions {
+            self.extensions = extensions
+                .iter()
+                .map(|e| e.trim_start_matches('.').to_lowercase())
+                .filter(|e| !e.is_empty())
+                .collect();
+        }
+        if let Some(date_sources) = file.date_sources {
+            self.date_sources = date_sources;
+        }
+        if let Some(duplicates) = file.duplicates {
+            self.duplicates = duplicates;
+        }
+        if !file.ignore.is_empty() {
+            scan::IgnoreRules::new(Path::new(""), &file.ignore).map_err(|e| format!("invalid ignore pattern: {}", e))?;
+            self.ignore.extend(file.ignore);
+        }
+        Ok(())
+    }
+
+    /// The effective settings as TOML, for `config show`.
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).unwrap_or_default()
+    }
+}
+
+/// A path or name with `{year}`, `{month}`, `{day}`, `{hour}`, `{minute}`
+/// and `{second}` placeholders, filled in zero-padded from the capture date.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Template(String);
+
+const PLACEHOLDERS: [&str; 6] = ["year", "month", "day", "hour", "minute", "second"];
+
+/// Year, month, day, hour, minute and second, in `PLACEHOLDERS` order.
+pub type DateParts = [u16; 6];
+
+enum Token<'a> {
+    Literal(&'a str),
+    /// Index into `PLACEHOLDERS`
+    Field(usize),
+}
+
+fn tokens(template: &str) -> Result<Vec<Token<'_>>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err(format!("unmatched '}}' in \"{}\"", template));
+        }
+        let Some(close) = rest[open..].find('}') else {
+            return Err(format!("unmatched '{{' in \"{}\"", template));
+        };
+        let name = &rest[open + 1..open + close];
+        let Some(field) = PLACEHOLDERS.iter().position(|p| *p == name) else {
+            return Err(format!(
+                "unknown placeholder {{{}}} in \"{}\", expected one of {}",
+                name,
+                template,
+                PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+            ));
+        };
+        if open > 0 {
+            tokens.push(Token::Literal(&rest[..open]));
+        }
+        tokens.push(Token::Field(field));
+        rest = &rest[open + close + 1..];
+    }
+    if !rest.is_empty() {
+        tokens.push(Token::Literal(rest));
+    }
+    Ok(tokens)
+}
+
+impl TryFrom<String> for Template {
+    type Error = String;
+
+    fn try_from(template: String) -> Result<Self, String> {
+        tokens(&template)?;
+        Ok(Template(template))
+    }
+}
+
+impl From<Template> for String {
+    fn from(template: Template) -> String {
+        template.0
+    }
+}
+
+impl std::fmt::Display for Template {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl Template {
+    /// Parse and check a layout, e.g. for `--layout`.
+    pub fn layout(template: &str) -> Result<Template, String> {
+        let layout = Template::try_from(template.to_string())?;
+        layout.check_layout()?;
+        Ok(layout)
+    }
+
+    /// Parse and check a naming template, e.g. for `--naming`.
+    pub fn naming(template: &str) -> Result<Template, String> {
+        let naming = Template::try_from(template.to_string())?;
+        naming.check_naming()?;
+        Ok(naming)
+    }
+
+    /// Fill in the placeholders, or `None` for an undated file.
+    pub fn render(&self, date: &DateExtracted) -> Option<String> {
+        let DateExtracted::Found {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            ..
+        } = *date
+        else {
+            return None;
+        };
+        Some(self.render_parts([year, month.into(), day.into(), hour.into(), minute.into(), second.into()]))
+    }
+
+    pub fn render_parts(&self, parts: DateParts) -> String {
+        // Validated on construction
+        let tokens = tokens(&self.0).unwrap_or_default();
+        let mut out = String::new();
+        for token in tokens {
+            match token {
+                Token::Literal(literal) => out.push_str(literal),
+                Token::Field(0) => out.push_str(&format!("{:04}", parts[0])),
+                Token::Field(field) => out.push_str(&format!("{:02}", parts[field])),
+            }
+        }
+        out
+    }
+
+    /// Read date parts back out of something this template rendered. Parts
+    /// the template does not mention are `None`.
+    pub fn parse(&self, rendered: &str) -> Option<[Option<u16>; 6]> {
+        let mut parts = [None; 6];
+        let mut rest = rendered;
+        for token in tokens(&self.0).ok()? {
+            match token {
+                Token::Literal(literal) => rest = rest.strip_prefix(literal)?,
+                Token::Field(field) => {
+                    let width = if field == 0 { 4 } else { 2 };
+                    let digits = rest.get(..width).filter(|d| d.bytes().all(|b| b.is_ascii_digit()))?;
+                    let value = digits.parse().ok()?;
+                    if parts[field].is_some_and(|seen| seen != value) {
+                        return None;
+                    }
+                    parts[field] = Some(value);
+                    rest = &rest[width..];
+                }
+            }
+        }
+        rest.is_empty().then_some(parts)
+    }
+
+    /// A layout must stay inside the target and clear of the folders the
+    /// importer manages itself.
+    fn check_layout(&self) -> Result<(), String> {
+        let path = Path::new(&self.0);
+        let first = path.components().next();
+        if !path
+            .components()
+            .all(|c| matches!(c, Component::Normal(name) if !name.to_string_lossy().starts_with('.')))
+        {
+            return Err(format!("layout \"{}\" must be a relative path without '..' or hidden folders", self.0));
+        }
+        if let Some(Component::Normal(first)) = first
+            && ["undated", "duplicates", "corrupt"].iter().any(|reserved| first == *reserved)
+        {
+            return Err(format!("layout \"{}\" must not start with a reserved folder", self.0));
+        }
+        Ok(())
+    }
+
+    fn check_naming(&self) -> Result<(), String> {
+        if self.0.is_empty() || self.0.contains(['/', '\\']) {
+            return Err(format!("naming \"{}\" must be a non-empty file name", self.0));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,85 @@
+use serde::Serialize;
+
+/// Version of the `--output=jsonl` schema, reported in the summary. Within
+/// a version, fields may be added but are never renamed, removed or retyped.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// One line of `--output=jsonl`. Every source file produces a `file` event;
+/// a single `summary` event ends the stream.
+#[derive(Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    File(FileEvent),
+    Summary(SummaryEvent),
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    Imported,
+    Undated,
+    Duplicate,
+    Corrupt,
+    Skipped,
+}
+
+impl Category {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Category::Imported => "imported",
+            Category::Undated => "undated",
+            Category::Duplicate => "duplicate",
+            Category::Corrupt => "corrupt",
+            Category::Skipped => "skipped",
+        }
+    }
+}
+
+/// What happened to one source file. Optional fields are `null` when they
+/// do not apply or are not known, e.g. `destination` for a skipped file.
+#[derive(Serialize, Clone)]
+pub struct FileEvent {
+    /// Source path as discovered under the import source
+    pub source: String,
+    pub category: Category,
+    /// Path the file was copied to, or would be with `--execute`
+    pub destination: Option<String>,
+    /// Hex content hash under `hash_algorithm`
+    pub hash: Option<String>,
+    pub hash_algorithm: Option<&'static str>,
+    /// Capture time as `YYYY-MM-DDTHH:MM:SS`, without time zone
+    pub date: Option<String>,
+    /// Where `date` came from, as in the manifest's `date_source`
+    pub date_source: Option<String>,
+    /// Library file this one duplicates
+    pub duplicate_of: Option<String>,
+    /// Why the file is corrupt or was skipped
+    pub error: Option<String>,
+    /// Whether the source was deleted, after `--move` or a verified
+    /// `--duplicates=delete-source`
+    pub source_removed: bool,
+    pub dry_run: bool,
+}
+
+/// Counts matching the text summary line.
+#[derive(Serialize)]
+pub struct SummaryEvent {
+    pub schema_version: u32,
+    pub imported: usize,
+    pub duplicates: usize,
+    pub corrupt: usize,
+    pub undated: usize,
+    pub skipped: usize,
+    /// Bytes the run expected to write into the target, from the
+    /// pre-flight space check
+    pub projected_bytes: u64,
+    pub dry_run: bool,
+}
+
+/// Write one event as a line on stdout.
+pub fn emit(event: &Event) {
+    match serde_json::to_string(event) {
+        Ok(line) => println!("{}", line),
+        Err(e) => eprintln!("WARNING: Failed to encode event: {}", e),
+    }
+}
@@ -0,0 +1,1640 @@
+use crate::concurrency::{self, Jobs};
+use crate::config::Template;
+use crate::library::{self, LibrarySettings};
+use crate::events::{self, FileEvent, SummaryEvent};
+use crate::metadata::{self, DateChain, Digests, HashAlgorithm, format_hash};
+use crate::throttle::{self, Bandwidth};
+use crate::{buffer, cache, index, manifest, scan, space, validate};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::ops::ControlFlow;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "kebab-case")]
+pub enum DuplicatePolicy {
+    /// Copy (or move) the file into duplicates/
+    Copy,
+    /// Write a duplicates/ manifest entry pointing at the canonical file, leave the source alone
+    Record,
+    /// Count the duplicate but leave no trace in the target
+    Skip,
+    /// Record the duplicate and remove the source once the canonical file's hash is confirmed (requires --move)
+    DeleteSource,
+}
+
+/// Receives progress and outcomes while an import runs. Methods are called
+/// from worker threads and all default to doing nothing.
+pub trait Observer: Sync {
+    /// A phase begins that will process `total_bytes` of source data.
+    fn phase(&self, _phase: Phase, _total_bytes: u64) {}
+    /// `bytes` more of the current phase are done.
+    fn advance(&self, _bytes: u64) {}
+    /// A source file's outcome is final. Called in source order within each
+    /// batch of files, skipped files first.
+    fn file(&self, _event: &FileEvent) {}
+    /// Something went wrong that does not stop the import.
+    fn warning(&self, _message: &str) {}
+}
+
+/// Observer that ignores everything.
+pub struct Silent;
+
+impl Observer for Silent {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    /// Hashing files that may be duplicates, before anything is written
+    Hashing,
+    /// Validating, copying and naming files
+    Importing,
+}
+
+/// Why an import could not run.
+#[derive(Debug)]
+pub enum ImportError {
+    /// `DuplicatePolicy::DeleteSource` without `move_files`
+    DeleteSourceRequiresMove,
+    /// More copy writers than read workers, which would never all run
+    CopyJobsExceedJobs { copy_jobs: usize, jobs: usize },
+    /// The pre-flight check found too little free space and `force` is off
+    InsufficientSpace { needed: u64, available: u64 },
+    /// The library's `.library.toml` could not be read
+    LibrarySettings(crate::config::ConfigError),
+    /// The layout or naming differs from the settings the library is bound to
+    LibraryMismatch { bound: LibrarySettings },
+    /// An ignore pattern is not valid gitignore syntax
+    IgnorePattern(ignore::Error),
+    /// Worker threads could not be started
+    ThreadPool(rayon::ThreadPoolBuildError),
+    /// The target lock is held by another import or migration, or could
+    /// not be taken
+    TargetLock(std::io::Error),
+}
+
+impl std::fmt::Display for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImportError::DeleteSourceRequiresMove => write!(f, "--duplicates=delete-source requires --move"),
+            ImportError::CopyJobsExceedJobs { copy_jobs, jobs } => write!(
+                f,
+                "--copy-jobs={} exceeds --jobs={}: copies run on the read workers",
+                copy_jobs, jobs
+            ),
+            ImportError::InsufficientSpace { needed, available } => write!(
+                f,
+                "not enough space in the target: this import needs {}, {} available",
+                indicatif::BinaryBytes(*needed),
+                indicatif::BinaryBytes(*available)
+            ),
+            ImportError::LibrarySettings(e) => write!(f, "invalid library settings {}", e),
+            ImportError::LibraryMismatch { bound } => write!(
+                f,
+                "the library is bound to layout \"{}\" and naming \"{}\"; change them with `image-organiser library migrate`",
+                bound.layout, bound.naming
+            ),
+            ImportError::IgnorePattern(e) => write!(f, "invalid ignore pattern: {}", e),
+            ImportError::ThreadPool(e) => write!(f, "failed to start worker threads: {}", e),
+            ImportError::TargetLock(e) if e.kind() == std::io::ErrorKind::WouldBlock => write!(f, "{}", e),
+            ImportError::TargetLock(e) => write!(f, "cannot lock the target: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for ImportError {}
+
+/// Result of a run that started.
+pub struct ImportOutcome {
+    pub summary: SummaryEvent,
+    /// Every source file's outcome, in the order they were reported
+    pub files: Vec<FileEvent>,
+    /// Set when the run stopped before every file was processed
+    pub stopped: Option<Stopped>,
+    /// Folders whose manifest could not be saved. The run stops after the
+    /// first; that batch's files there are taken out again, their sources
+    /// kept, and they are left out of the run.
+    pub unsaved_manifests: Vec<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    /// The cancel flag was raised
+    Interrupted,
+    /// A copy found the target full. The remaining files were left in
+    /// place; they may need up to `remaining_bytes`.
+    DiskFull { remaining_files: usize, remaining_bytes: u64 },
+    /// A manifest could not be saved; see `ImportOutcome::unsaved_manifests`
+    ManifestNotSaved,
+}
+
+/// Imports media from a source directory into a library. Built with
+/// `Importer::new` and the setter methods, then started with `run`; by
+/// default it performs a dry run.
+#[derive(Debug, Clone)]
+pub struct Importer {
+    source: PathBuf,
+    target: PathBuf,
+    execute: bool,
+    move_files: bool,
+    duplicates: DuplicatePolicy,
+    use_cache: bool,
+    algorithm: HashAlgorithm,
+    jobs: Jobs,
+    copy_jobs: Jobs,
+    max_bandwidth: Option<Bandwidth>,
+    force: bool,
+    dates: DateChain,
+    layout: Template,
+    naming: Template,
+    extensions: Vec<String>,
+    ignore: Vec<String>,
+    cancel: Arc<AtomicBool>,
+    free_space: Option<u64>,
+}
+
+impl Importer {
+    pub fn new(source: impl Into<PathBuf>, target: impl Into<PathBuf>) -> Self {
+        let defaults = crate::config::Config::default();
+        Importer {
+            source: source.into(),
+            target: target.into(),
+            execute: false,
+            move_files: false,
+            duplicates: DuplicatePolicy::Copy,
+            use_cache: true,
+            algorithm: HashAlgorithm::Sha256,
+            jobs: Jobs::Auto,
+            copy_jobs: Jobs::Auto,
+            max_bandwidth: None,
+            force: false,
+            dates: DateChain::default(),
+            layout: defaults.layout,
+            naming: defaults.naming,
+            extensions: defaults.extensions,
+            ignore: defaults.ignore,
+            cancel: Arc::new(AtomicBool::new(false)),
+            free_space: None,
+        }
+    }
+
+    /// Write to the target instead of only reporting what would happen.
+    pub fn execute(mut self, execute: bool) -> Self {
+        self.execute = execute;
+        self
+    }
+
+    /// Remove each source once its copy is verified.
+    pub fn move_files(mut self, move_files: bool) -> Self {
+        self.move_files = move_files;
+        self
+    }
+
+    pub fn duplicates(mut self, policy: DuplicatePolicy) -> Self {
+        self.duplicates = policy;
+        self
+    }
+
+    /// Consult and update the source hash cache in the target.
+    pub fn use_cache(mut self, use_cache: bool) -> Self {
+        self.use_cache = use_cache;
+        self
+    }
+
+    /// Content hash for new manifest entries.
+    pub fn hash_algorithm(mut self, algorithm: HashAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Hashing and metadata workers.
+    pub fn jobs(mut self, jobs: Jobs) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Concurrent copy writers. Copies run on the read workers, so this can
+    /// be at most `jobs`; a worker waiting for a copy slot reads nothing else.
+    pub fn copy_jobs(mut self, copy_jobs: Jobs) -> Self {
+        self.copy_jobs = copy_jobs;
+        self
+    }
+
+    /// Limit on the combined write rate into the target.
+    pub fn max_bandwidth(mut self, max_bandwidth: Option<Bandwidth>) -> Self {
+        self.max_bandwidth = max_bandwidth;
+        self
+    }
+
+    /// Start even if the target looks too small.
+    pub fn force(mut self, force: bool) -> Self {
+        self.force = force;
+        self
+    }
+
+    /// Where capture dates come from, in priority order.
+    pub fn date_chain(mut self, dates: DateChain) -> Self {
+        self.dates = dates;
+        self
+    }
+
+    /// Directory under the target for dated files, e.g. `{year}/{month}`.
+    pub fn layout(mut self, layout: Template) -> Self {
+        self.layout = layout;
+        self
+    }
+
+    /// File name for dated files, without extension.
+    pub fn naming(mut self, naming: Template) -> Self {
+        self.naming = naming;
+        self
+    }
+
+    /// Extensions to import, lowercase without a dot; other files are
+    /// reported as skipped.
+    pub fn extensions(mut self, extensions: Vec<String>) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Gitignore-style patterns, relative to the source, for files to leave
+    /// out of the import without reporting them. They apply after the
+    /// built-in ignores and the source's `.organiserignore`.
+    pub fn ignore(mut self, patterns: Vec<String>) -> Self {
+        self.ignore = patterns;
+        self
+    }
+
+    /// Flag that stops the run when raised: files in progress finish and
+    /// are recorded, the rest are left for the next run.
+    pub fn cancel_flag(mut self, cancel: Arc<AtomicBool>) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    /// Act as if the target had only `bytes` free: the space check before
+    /// the import uses it, and a copy that would go past it fails as if the
+    /// disk were full. For exercising a full target without filling a real
+    /// disk.
+    pub fn simulate_free_space(mut self, bytes: Option<u64>) -> Self {
+        self.free_space = bytes;
+        self
+    }
+
+    pub fn run(&self, observer: &dyn Observer) -> Result<ImportOutcome, ImportError> {
+        if self.duplicates == DuplicatePolicy::DeleteSource && !self.move_files {
+            return Err(ImportError::DeleteSourceRequiresMove);
+        }
+        if let (Jobs::Fixed(copy_jobs), Jobs::Fixed(jobs)) = (self.copy_jobs, self.jobs)
+            && copy_jobs > jobs
+        {
+            return Err(ImportError::CopyJobsExceedJobs {
+                copy_jobs: copy_jobs.get(),
+                jobs: jobs.get(),
+            });
+        }
+        let (source, target, execute) = (&self.source, &self.target, self.execute);
+        // Concurrent runs would overwrite each other's manifest updates
+        let _lock = execute
+            .then(|| library::lock_target(target))
+            .transpose()
+            .map_err(ImportError::TargetLock)?;
+        let settings = LibrarySettings::new(self.layout.clone(), self.naming.clone());
+        let bound = library::bound_settings(target).map_err(ImportError::LibrarySettings)?;
+        if let Some(bound) = bound.as_ref().filter(|bound| **bound != settings) {
+            return Err(ImportError::LibraryMismatch { bound: bound.clone() });
+        }
+
+        let mut file_events: Vec<FileEvent> = Vec::new();
+        let mut report = |event: FileEvent| {
+            observer.file(&event);
+            file_events.push(event);
+        };
+        let ignore = scan::IgnoreRules::for_source(source, &self.ignore).map_err(ImportError::IgnorePattern)?;
+        let files = scan::discover_files(source, &ignore);
+        let mut library_index = open_library_index(target, execute, observer);
+        let dedup_index = library_index
+            .as_ref()
+            .and_then(|ix| ix.dedup_index().ok())
+            .unwrap_or_else(|| manifest::build_dedup_index(target, &|message| observer.warning(message)));
+        let hash_cache = self
+            .use_cache
+            .then(|| RwLock::new(cache::load_cache(target, &|message| observer.warning(message))));
+        // Hash with the chosen algorithm first, plus any other the library
+        // already uses so mixed libraries still dedup
+        let library_algorithms = dedup_index.algorithms();
+        let algorithms: Vec<HashAlgorithm> = std::iter::once(self.algorithm)
+            .chain(HashAlgorithm::ALL.into_iter().filter(|a| *a != self.algorithm && library_algorithms.contains(a)))
+            .collect();
+
+        let mut recognized: Vec<(PathBuf, String)> = Vec::new();
+        let mut skipped_count: usize = 0;
+        for file in &files {
+            match scan::classify_file_with(file, &self.extensions) {
+                scan::MediaFile::Recognized { path, extension } => {
+                    recognized.push((path, extension));
+                }
+                scan::MediaFile::Unrecognized { path, extension } => {
+                    let reason = if extension.is_empty() {
+                        "no extension".to_string()
+                    } else {
+                        format!(".{} unrecognized", extension)
+                    };
+                    report(skipped_event(&path, reason, execute));
+                    skipped_count += 1;
+                }
+            }
+        }
+        // Source order decides which of several identical files is canonical
+        recognized.sort();
+
+        // Reads (hashing, metadata, validation) run on the pool; a worker
+        // then copies its own file, holding one of fewer copy slots
+        let parallel = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let read_jobs = self.jobs.resolve(source, parallel);
+        let copy_jobs = self.copy_jobs.resolve(target, read_jobs).min(read_jobs);
+        let run = Run {
+            importer: self,
+            observer,
+            algorithms,
+            dedup_index: Mutex::new(dedup_index),
+            copy_slots: concurrency::Semaphore::new(copy_jobs),
+            throttle: self.max_bandwidth.map(throttle::Throttle::new),
+            disk_full: AtomicBool::new(false),
+            unrecorded: AtomicBool::new(false),
+            swept_dirs: Mutex::new(std::collections::HashSet::new()),
+            free_space: self.free_space.map(AtomicU64::new),
+        };
+        let names = manifest::NameAllocator::new(execute);
+        // Recorded duplicates hold manifest keys in duplicates/ with no file
+        // behind them; later copies and records must not reuse those keys
+        let dup_dir = target.join("duplicates");
+        names.reserve(
+            &dup_dir,
+            manifest::load_manifest(&dup_dir, &|message| observer.warning(message)).files.into_keys(),
+        );
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(read_jobs)
+            .build()
+            .map_err(ImportError::ThreadPool)?;
+
+        // Only a file sharing its size with a library file or another
+        // candidate can be a duplicate. Those are hashed first so
+        // intra-batch duplicates are known before any file claims a
+        // destination; the rest are hashed while being copied.
+        let sizes: Vec<Option<u64>> = pool.install(|| {
+            recognized
+                .par_iter()
+                .map(|(path, _)| path.metadata().ok().map(|m| m.len()))
+                .collect()
+        });
+        let mut size_counts: std::collections::HashMap<u64, usize> = std::collections::HashMap::new();
+        for size in sizes.iter().flatten() {
+            *size_counts.entry(*size).or_default() += 1;
+        }
+        // Progress is tracked in bytes so throughput and ETA reflect
+        // large videos and small photos alike
+        let total_bytes = sizes.iter().flatten().sum();
+
+        let needs_hash = |size: &Option<u64>| match size {
+            Some(size) => {
+                !execute
+                    || size_counts[size] > 1
+                    || run.dedup_index.lock().unwrap().contains_size(*size)
+            }
+            None => true,
+        };
+
+        observer.phase(Phase::Hashing, total_bytes);
+        let hashed: Vec<Hashed> = pool.install(|| {
+            recognized
+                .par_iter()
+                .zip(&sizes)
+                .map(|((path, _), size)| {
+                    if run.stopping() {
+                        return None;
+                    }
+                    let hash = needs_hash(size).then(|| hash_with_cache(path, &run.algorithms, hash_cache.as_ref()));
+                    observer.advance(size.unwrap_or(0));
+                    hash
+                })
+                .collect()
+        });
+
+        // First occurrence of each hash is processed in the first wave; later
+        // copies wait for the second wave and find it in the dedup index
+        let mut seen = std::collections::HashSet::new();
+        let (first_wave, second_wave): (Vec<_>, Vec<_>) = recognized
+            .iter()
+            .zip(hashed.iter().zip(&sizes))
+            .partition(|(_, (hash, _))| match hash {
+                Some(Ok((h, _))) => seen.insert(*h),
+                _ => true,
+            });
+
+        // Bytes this run will write: every file except duplicates, which
+        // are only written under --duplicates=copy. Corrupt files count
+        // too, since they are copied into quarantine.
+        let projected_bytes: u64 = {
+            let library = run.dedup_index.lock().unwrap();
+            let first = first_wave
+                .iter()
+                .map(|(_, (hashed, size))| (matches!(hashed, Some(Ok((d, _))) if library.find(d).is_some()), size));
+            let second = second_wave
+                .iter()
+                .map(|(_, (hashed, size))| (matches!(hashed, Some(Ok(_))), size));
+            first
+                .chain(second)
+                .filter(|(duplicate, _)| !duplicate || self.duplicates == DuplicatePolicy::Copy)
+                .map(|(_, size)| size.unwrap_or(0))
+                .sum()
+        };
+        if execute
+            && let Some(available) = self.free_space.or_else(|| space::available_bytes(target))
+            && available < projected_bytes {
+            if !self.force {
+                return Err(ImportError::InsufficientSpace {
+                    needed: projected_bytes,
+                    available,
+                });
+            }
+            observer.warning(&format!(
+                "Not enough space in {}: this import needs {}, {} available",
+                target.display(),
+                indicatif::BinaryBytes(projected_bytes),
+                indicatif::BinaryBytes(available)
+            ));
+        }
+
+        // The first import binds the library to its layout and naming, and
+        // records the defaults an older library was implicitly bound to
+        if execute
+            && !library::settings_path(target).exists()
+            && let Err(e) = library::save_settings(target, &settings) {
+            observer.warning(&format!("Failed to save library settings in {}: {}", target.display(), e));
+        }
+
+        // Parallel processing, one wave after the other, in batches. Each
+        // batch's files are named once all of them are copied, then its
+        // manifests are saved, and only then are sources removed, so a
+        // crash leaves at most one batch of partial copies and never loses
+        // a source. After a stop, files not yet started are left out.
+        observer.phase(Phase::Importing, total_bytes);
+        let process = |((path, extension), (hashed, size)): &(&(PathBuf, String), (&Hashed, &Option<u64>))| {
+            if run.stopping() {
+                return None;
+            }
+            let result = run.process_file(path, extension, hashed);
+
+            // Thread-safe progress update
+            observer.advance(size.unwrap_or(0));
+
+            Some((path.clone(), result?))
+        };
+        let mut sources: Vec<PathBuf> = Vec::new();
+        let mut results: Vec<FileProcessingResult> = Vec::new();
+        let mut manifests: HashMap<PathBuf, manifest::Manifest> = HashMap::new();
+        let mut unsaved_manifests: Vec<PathBuf> = Vec::new();
+        for batch in first_wave.chunks(PLACE_BATCH).chain(second_wave.chunks(PLACE_BATCH)) {
+            let (mut batch_sources, mut batch_results): (Vec<_>, Vec<_>) =
+                pool.install(|| batch.par_iter().filter_map(process).unzip());
+            run.place_batch(&mut batch_results, &names);
+            if execute {
+                let (saved, unsaved) = run.record_batch(&mut batch_sources, &mut batch_results, &mut manifests);
+                let saved: Vec<_> = saved.into_iter().map(|dir| (dir.clone(), &manifests[&dir])).collect();
+                if let Some(library_index) = &mut library_index
+                    && let Err(e) = library_index.record_manifests(&saved) {
+                    observer.warning(&format!("Failed to update library index: {}", e));
+                }
+                unsaved_manifests.extend(unsaved);
+            }
+            for (path, result) in batch_sources.iter().zip(&batch_results) {
+                report(run.file_event(path, result));
+            }
+            sources.extend(batch_sources);
+            results.extend(batch_results);
+        }
+
+        // Cache hashes of sources that are still in place for the next run
+        if execute && let Some(hash_cache) = &hash_cache {
+            let mut hash_cache = hash_cache.write().unwrap();
+            for manifest_entry in results.iter().filter_map(|r| r.manifest_entry()) {
+                let file_entry = &manifest_entry.entry;
+                if let Some(fingerprint) = manifest_entry.fingerprint
+                    && !file_entry.hash.is_empty()
+                {
+                    let original = Path::new(&file_entry.original_path);
+                    hash_cache.record(original, fingerprint, file_entry.hash_algorithm, &file_entry.hash);
+                }
+            }
+            if let Err(e) = cache::save_cache(target, &hash_cache) {
+                observer.warning(&format!("Failed to save hash cache in {}: {}", target.display(), e));
+            }
+        }
+
+        let stopped = if run.disk_full.load(Ordering::Relaxed) {
+            let placed: std::collections::HashSet<&PathBuf> = sources.iter().collect();
+            let (remaining_files, remaining_bytes) = recognized
+                .iter()
+                .zip(&sizes)
+                .filter(|((path, _), _)| !placed.contains(path))
+                .fold((0, 0), |(count, bytes), (_, size)| (count + 1, bytes + size.unwrap_or(0)));
+            Some(Stopped::DiskFull {
+                remaining_files,
+                remaining_bytes,
+            })
+        } else if run.unrecorded.load(Ordering::Relaxed) {
+            Some(Stopped::ManifestNotSaved)
+        } else if self.cancel.load(Ordering::Relaxed) {
+            Some(Stopped::Interrupted)
+        } else {
+            None
+        };
+
+        let count = |matches: fn(&FileProcessingResult) -> bool| results.iter().filter(|r| matches(r)).count();
+        let summary = SummaryEvent {
+            schema_version: events::SCHEMA_VERSION,
+            imported: count(|r| matches!(r, FileProcessingResult::Imported { .. })),
+            duplicates: count(|r| matches!(r, FileProcessingResult::Duplicate { .. })),
+            corrupt: count(|r| matches!(r, FileProcessingResult::Corrupt { .. })),
+            undated: count(|r| matches!(r, FileProcessingResult::Undated { .. })),
+            skipped: skipped_count,
+            projected_bytes,
+            dry_run: !execute,
+        };
+        Ok(ImportOutcome {
+            summary,
+            files: file_events,
+            stopped,
+            unsaved_manifests,
+        })
+    }
+}
+
+/// Current time, or `SOURCE_DATE_EPOCH` when set so repeated imports can
+/// produce identical manifests.
+pub fn now_iso8601() -> String {
+    let now = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse().ok())
+        .and_then(|seconds| jiff::Timestamp::from_second(seconds).ok())
+        .unwrap_or_else(jiff::Timestamp::now);
+    now.strftime("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[derive(Debug, Clone)]
+struct ManifestEntry {
+    dir: PathBuf,
+    filename: String,
+    entry: manifest::FileEntry,
+    /// Set until the file is given its final name in `place_batch`
+    pending: Option<PendingName>,
+    /// Capture time for reporting, e.g. `2024-01-15T14:30:22`
+    captured: Option<String>,
+    /// Whether `place_batch` put a file behind this entry
+    placed: bool,
+    /// Source to remove once the entry's manifest is saved
+    removal: Option<SourceRemoval>,
+    source_removed: bool,
+    /// Source fingerprint from before the bytes behind `entry.hash` were
+    /// read, for the hash cache
+    fingerprint: Option<cache::Fingerprint>,
+}
+
+/// A source deleted after its import is recorded.
+#[derive(Debug, Clone)]
+enum SourceRemoval {
+    /// `--move`: removed once its library copy is in place
+    Moved(PathBuf),
+    /// `--duplicates=delete-source`: removed once the canonical library
+    /// file still holds the content it was matched on
+    Duplicate {
+        source: PathBuf,
+        canonical: PathBuf,
+        algorithm: HashAlgorithm,
+        hash: [u8; 32],
+        /// The source's fingerprint when `hash` was taken
+        fingerprint: Option<cache::Fingerprint>,
+    },
+}
+
+/// Sort key deciding which of several files competing for a name gets it:
+/// capture time, then content hash, then source path.
+type PlacementOrder = (Option<(u16, u8, u8, u8, u8, u8)>, [u8; 32], PathBuf);
+
+/// A file copied under a temporary name (or, in a dry run, not copied at
+/// all) that is waiting for its final name.
+#[derive(Debug, Clone)]
+struct PendingName {
+    source: PathBuf,
+    partial: Option<PathBuf>,
+    /// A manifest entry without a file, named among the manifest's keys
+    record: bool,
+    candidates: Vec<String>,
+    order: PlacementOrder,
+    remove_source: bool,
+}
+
+#[derive(Debug)]
+enum FileProcessingResult {
+    Imported {
+        manifest_entry: Option<ManifestEntry>,
+    },
+    Duplicate {
+        manifest_entry: Option<ManifestEntry>,
+        canonical: PathBuf,
+        hash: String,
+    },
+    Undated {
+        manifest_entry: Option<ManifestEntry>,
+    },
+    Corrupt {
+        manifest_entry: Option<ManifestEntry>,
+        error: String,
+    },
+}
+
+impl FileProcessingResult {
+    fn manifest_entry(&self) -> Option<&ManifestEntry> {
+        match self {
+            FileProcessingResult::Imported { manifest_entry }
+            | FileProcessingResult::Duplicate { manifest_entry, .. }
+            | FileProcessingResult::Undated { manifest_entry }
+            | FileProcessingResult::Corrupt { manifest_entry, .. } => manifest_entry.as_ref(),
+        }
+    }
+
+    fn manifest_entry_mut(&mut self) -> Option<&mut ManifestEntry> {
+        match self {
+            FileProcessingResult::Imported { manifest_entry }
+            | FileProcessingResult::Duplicate { manifest_entry, .. }
+            | FileProcessingResult::Undated { manifest_entry }
+            | FileProcessingResult::Corrupt { manifest_entry, .. } => manifest_entry.as_mut(),
+        }
+    }
+}
+
+/// Up-front hash of a source file: `None` when hashing was deferred to the
+/// copy, otherwise the digests with the source's fingerprint from before it
+/// was read, or the error that prevented reading it.
+type Hashed = Option<std::io::Result<(Digests, Option<cache::Fingerprint>)>>;
+
+fn capture_key(date: &metadata::DateExtracted) -> Option<(u16, u8, u8, u8, u8, u8)> {
+    match *date {
+        metadata::DateExtracted::Found {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+            ..
+        } => Some((year, month, day, hour, minute, second)),
+        metadata::DateExtracted::NotFound => None,
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_manifest_entry(
+    dir: &Path,
+    filename: &str,
+    hex_hash: &str,
+    hash_algorithm: HashAlgorithm,
+    source_path: &Path,
+    original_name: &str,
+    date_source: Option<&str>,
+    source_group: Option<&str>,
+) -> ManifestEntry {
+    let file_size = source_path
+        .metadata()
+        .map(|m| m.len())
+        .unwrap_or(0);
+
+    ManifestEntry {
+        dir: dir.to_path_buf(),
+        filename: filename.to_string(),
+        entry: manifest::FileEntry {
+            hash: hex_hash.to_string(),
+            hash_algorithm,
+            original_path: source_path.to_string_lossy().into_owned(),
+            original_name: original_name.to_string(),
+            date_source: date_source.map(|s| s.to_string()),
+            source_group: source_group.map(|s| s.to_string()),
+            duplicate_of: None,
+            error_kind: None,
+            corrupt_reason: None,
+            imported_at: now_iso8601(),
+            file_size_bytes: file_size,
+        },
+        pending: None,
+        captured: None,
+        placed: false,
+        removal: None,
+        source_removed: false,
+        fingerprint: None,
+    }
+}
+
+/// Manifest `error_kind` for an I/O error. The names are part of the
+/// manifest format, so they are spelled out rather than derived from the
+/// standard library's `Debug` output; kinds not listed are `other`.
+fn error_kind_string(kind: std::io::ErrorKind) -> &'static str {
+    use std::io::ErrorKind;
+    match kind {
+        ErrorKind::InvalidData => "invalid_data",
+        ErrorKind::UnexpectedEof => "unexpected_eof",
+        ErrorKind::PermissionDenied => "permission_denied",
+        ErrorKind::NotFound => "not_found",
+        ErrorKind::IsADirectory => "is_a_directory",
+        ErrorKind::Interrupted => "interrupted",
+        ErrorKind::TimedOut => "timed_out",
+        ErrorKind::ResourceBusy => "resource_busy",
+        ErrorKind::StaleNetworkFileHandle => "stale_network_file_handle",
+        _ => "other",
+    }
+}
+
+/// Open the library's SQLite index and bring it up to date with the
+/// manifests. Dry runs never create or modify it: they use an existing index
+/// only if it is already fresh, and otherwise read manifests directly.
+fn open_library_index(target: &Path, execute: bool, observer: &dyn Observer) -> Option<index::LibraryIndex> {
+    if !execute {
+        return index::LibraryIndex::open_read_only(target)
+            .ok()
+            .filter(|ix| ix.is_fresh().unwrap_or(false));
+    }
+    let opened = std::fs::create_dir_all(target)
+        .map_err(|e| e.to_string())
+        .and_then(|()| index::LibraryIndex::open(target).map_err(|e| e.to_string()))
+        .and_then(|mut ix| {
+            ix.refresh(&|message| observer.warning(message))
+                .map(|_| ix)
+                .map_err(|e| e.to_string())
+        });
+    match opened {
+        Ok(ix) => Some(ix),
+        Err(e) => {
+            observer.warning(&format!(
+                "Library index unavailable in {} ({}), reading manifests",
+                target.display(),
+                e
+            ));
+            None
+        }
+    }
+}
+
+/// Hash `path` with every algorithm in `algorithms`, consulting and updating
+/// the source hash cache when enabled.
+fn hash_with_cache(
+    path: &Path,
+    algorithms: &[HashAlgorithm],
+    cache: Option<&RwLock<cache::HashCache>>,
+) -> std::io::Result<(Digests, Option<cache::Fingerprint>)> {
+    let fingerprint = cache::fingerprint(path);
+    if let (Some(cache), Some(fingerprint)) = (cache, fingerprint)
+        && let Some(digests) = cache.read().unwrap().lookup(path, fingerprint, algorithms) {
+        return Ok((digests, Some(fingerprint)));
+    }
+    let digests = metadata::hash_file(path, algorithms)?;
+    if let (Some(cache), Some(fingerprint)) = (cache, fingerprint) {
+        let mut cache = cache.write().unwrap();
+        for (algorithm, digest) in digests.iter() {
+            cache.record(path, fingerprint, algorithm, &format_hash(&digest));
+        }
+    }
+    Ok((digests, fingerprint))
+}
+
+fn hash_one(path: &Path, algorithm: HashAlgorithm) -> std::io::Result<[u8; 32]> {
+    metadata::hash_file(path, &[algorithm]).map(|digests| digests.get(algorithm).unwrap_or_default())
+}
+
+fn skipped_event(path: &Path, reason: String, execute: bool) -> FileEvent {
+    FileEvent {
+        source: path.display().to_string(),
+        category: events::Category::Skipped,
+        destination: None,
+        hash: None,
+        hash_algorithm: None,
+        date: None,
+        date_source: None,
+        duplicate_of: None,
+        error: Some(reason),
+        source_removed: false,
+        dry_run: !execute,
+    }
+}
+
+/// State shared by every worker of one run.
+struct Run<'a> {
+    importer: &'a Importer,
+    observer: &'a dyn Observer,
+    /// The chosen algorithm first, then any other the library uses
+    algorithms: Vec<HashAlgorithm>,
+    dedup_index: Mutex<manifest::DedupIndex>,
+    copy_slots: concurrency::Semaphore,
+    throttle: Option<throttle::Throttle>,
+    /// Set when a copy fails because the target is full
+    disk_full: AtomicBool,
+    /// Set when a manifest could not be saved
+    unrecorded: AtomicBool,
+    /// Folders already cleared of partial copies left by earlier runs
+    swept_dirs: Mutex<std::collections::HashSet<PathBuf>>,
+    /// Bytes left under `Importer::simulate_free_space`
+    free_space: Option<AtomicU64>,
+}
+
+impl Run<'_> {
+    /// Whether workers should stop picking up new files.
+    fn stopping(&self) -> bool {
+        self.importer.cancel.load(Ordering::Relaxed)
+            || self.disk_full.load(Ordering::Relaxed)
+            || self.unrecorded.load(Ordering::Relaxed)
+    }
+
+    fn algorithm(&self) -> HashAlgorithm {
+        self.importer.algorithm
+    }
+
+    /// Hash, deduplicate, validate, date and copy one file. `None` means the
+    /// run stopped before this file could be placed, e.g. because the
+    /// target filled up; it is left for the next run.
+    fn process_file(&self, path: &Path, extension: &str, hashed: &Hashed) -> Option<FileProcessingResult> {
+        let execute = self.importer.execute;
+        // Extract source_group from filename
+        let source_group = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .and_then(scan::extract_source_group);
+
+        // Step 1: Hash file (done up front by the caller or taken from the
+        // cache, otherwise from the buffered contents or during a streamed
+        // copy). `algorithms` covers the chosen algorithm plus every one
+        // already used in the library.
+        let (known, hashed_fingerprint) = match hashed {
+            Some(Ok((d, fingerprint))) => (Some(*d), *fingerprint),
+            None => (None, None),
+            Some(Err(err)) => {
+                if err.kind() == std::io::ErrorKind::NotFound {
+                    self.observer.warning(&format!("Source file disappeared: {}", path.display()));
+                    return Some(FileProcessingResult::Corrupt {
+                        manifest_entry: None,
+                        error: "source file disappeared".to_string(),
+                    });
+                }
+                let manifest_entry = if execute {
+                    self.quarantine_corrupt(path, err, None, source_group.as_deref(), false)
+                } else {
+                    None
+                };
+                return Some(FileProcessingResult::Corrupt {
+                    manifest_entry,
+                    error: err.to_string(),
+                });
+            }
+        };
+
+        // Step 2: Check for duplicates under any algorithm the library uses.
+        // Files the caller did not hash have a size no library or batch file
+        // shares, so they cannot be duplicates. This needs only the digests,
+        // so a duplicate found through the cache is never read.
+        if let Some(known) = &known
+            && let ControlFlow::Break(result) =
+                self.check_duplicate(path, known, hashed_fingerprint, None, source_group.as_deref())
+        {
+            return result;
+        }
+
+        // Read once; validation, date extraction and the copy all work from
+        // the same buffer
+        let read_fingerprint = cache::fingerprint(path);
+        buffer::with_contents(path, |contents| {
+            self.import_file(path, extension, contents, known, read_fingerprint, source_group.as_deref())
+        })
+    }
+
+    /// Validate, date and copy a file that is not a known duplicate.
+    /// `known` holds its digests if it was hashed before being read, and
+    /// `fingerprint` is the source's fingerprint from before this read.
+    fn import_file(
+        &self,
+        path: &Path,
+        extension: &str,
+        contents: Option<&[u8]>,
+        known: Option<Digests>,
+        fingerprint: Option<cache::Fingerprint>,
+        source_group: Option<&str>,
+    ) -> Option<FileProcessingResult> {
+        let Importer {
+            target,
+            execute,
+            move_files,
+            ..
+        } = self.importer;
+        let (execute, move_files) = (*execute, *move_files);
+        let algorithm = self.algorithm();
+
+        // Buffered bytes are what gets written, so they are hashed again:
+        // the file may have changed since it was hashed or cached
+        let digests = match contents {
+            Some(bytes) => {
+                let buffered = metadata::hash_bytes(bytes, &self.algorithms);
+                if known.is_some_and(|known| known != buffered) {
+                    self.observer.warning(&format!(
+                        "Source changed during import, using current content: {}",
+                        path.display()
+                    ));
+                    if let ControlFlow::Break(result) =
+                        self.check_duplicate(path, &buffered, fingerprint, contents, source_group)
+                    {
+                        return result;
+                    }
+                }
+                Some(buffered)
+            }
+            None => known,
+        };
+        let hash = digests.and_then(|d| d.get(algorithm));
+        let hex_hash = hash.as_ref().map(format_hash);
+
+        // Step 3: Validate container structure
+        let validated = match contents {
+            Some(bytes) => validate::validate_bytes(bytes),
+            None => validate::validate_structure(path),
+        };
+        if let Err(err) = validated {
+            if err.kind() == std::io::ErrorKind::NotFound {
+                self.observer.warning(&format!("Source file disappeared: {}", path.display()));
+                return Some(FileProcessingResult::Corrupt {
+                    manifest_entry: None,
+                    error: "source file disappeared".to_string(),
+                });
+            }
+            let manifest_entry = if execute {
+                let hex_hash = hex_hash.or_else(|| hash_one(path, algorithm).ok().map(|h| format_hash(&h)));
+                self.quarantine_corrupt(path, &err, hex_hash.as_deref(), source_group, move_files)
+            } else {
+                None
+            };
+            return Some(FileProcessingResult::Corrupt {
+                manifest_entry,
+                error: err.to_string(),
+            });
+        }
+
+        // Step 4: Extract date
+        let date = self.importer.dates.extract(&metadata::DateInput { path, contents });
+        let (dest_dir, date_source) = match (&date, self.importer.layout.render(&date)) {
+            (metadata::DateExtracted::Found { source, .. }, Some(layout)) => (target.join(layout), Some(source.as_str())),
+            _ => (target.join("undated"), None),
+        };
+        let candidates_for = |hash: &[u8; 32]| match self.importer.naming.render(&date) {
+            Some(base) => manifest::filename_candidates(&base, extension, hash),
+            None => {
+                let original_stem = path
+                    .file_stem()
+                    .map(|s| s.to_string_lossy().into_owned())
+                    .unwrap_or_default();
+                manifest::suffixed_candidates(&original_stem, extension, hash)
+            }
+        };
+        let categorize = |mut manifest_entry: Option<ManifestEntry>| {
+            if let Some(manifest_entry) = &mut manifest_entry {
+                manifest_entry.captured = capture_key(&date).map(|(year, month, day, hour, minute, second)| {
+                    format!(
+                        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+                        year, month, day, hour, minute, second
+                    )
+                });
+            }
+            Some(match date_source {
+                Some(_) => FileProcessingResult::Imported { manifest_entry },
+                None => FileProcessingResult::Undated { manifest_entry },
+            })
+        };
+
+        let original_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "unknown".to_string());
+        let pending = |hash: [u8; 32], partial: Option<PathBuf>| PendingName {
+            source: path.to_path_buf(),
+            partial,
+            record: false,
+            candidates: candidates_for(&hash),
+            order: (capture_key(&date), hash, path.to_path_buf()),
+            remove_source: move_files,
+        };
+
+        // Step 5: Copy under a temporary name. The final name is assigned once
+        // the whole batch is copied, so it never depends on thread timing.
+        if !execute {
+            let hash = match hash.map(Ok).unwrap_or_else(|| hash_one(path, algorithm)) {
+                Ok(h) => h,
+                Err(err) => {
+                    return Some(FileProcessingResult::Corrupt {
+                        manifest_entry: None,
+                        error: err.to_string(),
+                    });
+                }
+            };
+            let mut manifest_entry = create_manifest_entry(
+                &dest_dir,
+                "",
+                &format_hash(&hash),
+                algorithm,
+                path,
+                &original_name,
+                date_source,
+                source_group,
+            );
+            manifest_entry.pending = Some(pending(hash, None));
+            return categorize(Some(manifest_entry));
+        }
+
+        let copied = {
+            let _permit = self.copy_slots.acquire();
+            self.copy_to_partial(path, contents.zip(hash), &dest_dir)
+        };
+        let (partial, copied_hash) = match copied {
+            Ok(copied) => copied,
+            Err(e) if is_disk_full(&e) => return None,
+            Err(e) => {
+                return Some(FileProcessingResult::Corrupt {
+                    manifest_entry: None,
+                    error: format!("copy failed: {}", e),
+                });
+            }
+        };
+        if hash.is_some_and(|h| h != copied_hash) {
+            self.observer.warning(&format!(
+                "Source changed during import, using copied content: {}",
+                path.display()
+            ));
+        }
+        let mut manifest_entry = create_manifest_entry(
+            &dest_dir,
+            "",
+            &format_hash(&copied_hash),
+            algorithm,
+            path,
+            &original_name,
+            date_source,
+            source_group,
+        );
+        manifest_entry.pending = Some(pending(copied_hash, Some(partial)));
+        manifest_entry.fingerprint = fingerprint;
+        categorize(Some(manifest_entry))
+    }
+
+    /// Handle a file whose `digests` match a library or quarantined file.
+    /// `Break` carries the file's outcome (`None` if the run stopped);
+    /// `Continue` means it is not a duplicate, or changed while being
+    /// copied, and should be imported. Without `contents` a copy into
+    /// `duplicates/` is streamed from disk.
+    fn check_duplicate(
+        &self,
+        path: &Path,
+        digests: &Digests,
+        fingerprint: Option<cache::Fingerprint>,
+        contents: Option<&[u8]>,
+        source_group: Option<&str>,
+    ) -> ControlFlow<Option<FileProcessingResult>> {
+        let Importer {
+            target,
+            execute,
+            move_files,
+            duplicates,
+            ..
+        } = self.importer;
+        let algorithm = self.algorithm();
+        let Some(hash) = digests.get(algorithm) else {
+            return ControlFlow::Continue(());
+        };
+        let hex_hash = format_hash(&hash);
+        let (existing, quarantined) = {
+            let index = self.dedup_index.lock().unwrap();
+            (
+                index.find(digests).map(|(matched, existing)| (matched, existing.clone())),
+                index.find_quarantined(digests).cloned(),
+            )
+        };
+        let Some((matched, existing)) = existing else {
+            // Content already in corrupt/ is not copied there again, and
+            // never becomes the canonical copy of a duplicate
+            return match quarantined {
+                Some(quarantined) => ControlFlow::Break(Some(FileProcessingResult::Corrupt {
+                    manifest_entry: None,
+                    error: format!(
+                        "already quarantined as {}",
+                        quarantined.strip_prefix(target).unwrap_or(&quarantined).display()
+                    ),
+                })),
+                None => ControlFlow::Continue(()),
+            };
+        };
+        let duplicate = |manifest_entry: Option<ManifestEntry>| {
+            ControlFlow::Break(Some(FileProcessingResult::Duplicate {
+                manifest_entry,
+                canonical: existing.clone(),
+                hash: hex_hash.clone(),
+            }))
+        };
+        if !*execute {
+            return duplicate(None);
+        }
+        let duplicate_of = existing
+            .strip_prefix(target)
+            .unwrap_or(&existing)
+            .to_string_lossy()
+            .into_owned();
+        let dup_dir = target.join("duplicates");
+        let original_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "unknown".to_string());
+        let mut manifest_entry =
+            create_manifest_entry(&dup_dir, "", &hex_hash, algorithm, path, &original_name, None, source_group);
+        manifest_entry.entry.duplicate_of = Some(duplicate_of);
+        match duplicates {
+            DuplicatePolicy::Copy => {
+                let copied = {
+                    let _permit = self.copy_slots.acquire();
+                    self.copy_to_partial(path, contents.map(|bytes| (bytes, hash)), &dup_dir)
+                };
+                match copied {
+                    Ok((partial, copied_hash)) if copied_hash != hash => {
+                        std::fs::remove_file(&partial).ok();
+                        self.observer.warning(&format!(
+                            "Source changed during import, using current content: {}",
+                            path.display()
+                        ));
+                        ControlFlow::Continue(())
+                    }
+                    Ok((partial, _)) => {
+                        manifest_entry.pending = Some(PendingName {
+                            source: path.to_path_buf(),
+                            partial: Some(partial),
+                            record: false,
+                            candidates: manifest::numbered_candidates(&original_name),
+                            order: (None, hash, path.to_path_buf()),
+                            remove_source: *move_files,
+                        });
+                        manifest_entry.fingerprint = fingerprint;
+                        duplicate(Some(manifest_entry))
+                    }
+                    Err(e) if is_disk_full(&e) => ControlFlow::Break(None),
+                    Err(e) => {
+                        if e.kind() != std::io::ErrorKind::NotFound {
+                            self.observer.warning(&format!("Failed to copy duplicate {}: {}", path.display(), e));
+                        }
+                        duplicate(None)
+                    }
+                }
+            }
+            DuplicatePolicy::Skip => duplicate(None),
+            DuplicatePolicy::Record | DuplicatePolicy::DeleteSource => {
+                // No file lands in duplicates/, but the manifest key is
+                // still allocated like a copied duplicate's name so two
+                // sources with the same name keep separate entries
+                manifest_entry.pending = Some(PendingName {
+                    source: path.to_path_buf(),
+                    partial: None,
+                    record: true,
+                    candidates: manifest::numbered_candidates(&original_name),
+                    order: (None, hash, path.to_path_buf()),
+                    remove_source: false,
+                });
+                if *duplicates == DuplicatePolicy::DeleteSource {
+                    manifest_entry.removal = Some(SourceRemoval::Duplicate {
+                        source: path.to_path_buf(),
+                        canonical: existing.clone(),
+                        algorithm: matched,
+                        hash: digests.get(matched).unwrap_or(hash),
+                        fingerprint,
+                    });
+                }
+                duplicate(Some(manifest_entry))
+            }
+        }
+    }
+
+    /// Copy a corrupt or unreadable file into `corrupt/` under a temporary
+    /// name and build its manifest entry; `place_batch` gives it its final
+    /// name. `hex_hash` is `None` when the file could not be hashed.
+    fn quarantine_corrupt(
+        &self,
+        path: &Path,
+        err: &std::io::Error,
+        hex_hash: Option<&str>,
+        source_group: Option<&str>,
+        move_files: bool,
+    ) -> Option<ManifestEntry> {
+        let corrupt_dir = self.importer.target.join("corrupt");
+        let original_name = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "unknown".to_string());
+        let copied = {
+            let _permit = self.copy_slots.acquire();
+            self.copy_to_partial(path, None, &corrupt_dir)
+        };
+        match copied {
+            Ok((partial, copied_hash)) => {
+                let mut manifest_entry = create_manifest_entry(
+                    &corrupt_dir,
+                    "",
+                    hex_hash.unwrap_or_default(),
+                    self.algorithm(),
+                    path,
+                    &original_name,
+                    None,
+                    source_group,
+                );
+                manifest_entry.entry.error_kind = Some(error_kind_string(err.kind()).to_string());
+                manifest_entry.entry.corrupt_reason = Some(err.to_string());
+                manifest_entry.pending = Some(PendingName {
+                    source: path.to_path_buf(),
+                    partial: Some(partial),
+                    record: false,
+                    candidates: manifest::numbered_candidates(&original_name),
+                    order: (None, copied_hash, path.to_path_buf()),
+                    remove_source: move_files,
+                });
+                Some(manifest_entry)
+            }
+            Err(e) => {
+                self.observer.warning(&format!("Failed to quarantine {}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    /// Give every file of a finished batch its final name and move it into
+    /// place. Files are named in capture-time, hash and source order rather
+    /// than the order workers finished them, so the same import always
+    /// produces the same names.
+    fn place_batch(&self, results: &mut [FileProcessingResult], names: &manifest::NameAllocator) {
+        let execute = self.importer.execute;
+        let order = |result: &FileProcessingResult| {
+            result
+                .manifest_entry()
+                .and_then(|entry| entry.pending.as_ref())
+                .map(|pending| pending.order.clone())
+        };
+        let mut waiting: Vec<&mut FileProcessingResult> = results
+            .iter_mut()
+            .filter(|result| order(result).is_some())
+            .collect();
+        waiting.sort_by_cached_key(|result| order(result));
+
+        for result in waiting {
+            let Some(manifest_entry) = result.manifest_entry_mut() else {
+                continue;
+            };
+            let Some(pending) = manifest_entry.pending.take() else {
+                continue;
+            };
+            let allocated = if pending.record {
+                names.allocate_record(&manifest_entry.dir, pending.candidates)
+            } else {
+                names.allocate(&manifest_entry.dir, pending.candidates)
+            };
+            let placed = allocated
+                .and_then(|dest| match &pending.partial {
+                    Some(partial) => std::fs::rename(partial, &dest).map(|()| dest.clone()).inspect_err(|_| {
+                        std::fs::remove_file(&dest).ok();
+                    }),
+                    None => Ok(dest),
+                });
+            let dest = match placed {
+                Ok(dest) => dest,
+                Err(e) => {
+                    if let Some(partial) = &pending.partial {
+                        std::fs::remove_file(partial).ok();
+                    }
+                    let error = if let FileProcessingResult::Corrupt { error, .. } = result {
+                        self.observer.warning(&format!("Failed to quarantine {}: {}", pending.source.display(), e));
+                        std::mem::take(error)
+                    } else {
+                        format!("copy failed: {}", e)
+                    };
+                    *result = FileProcessingResult::Corrupt {
+                        manifest_entry: None,
+                        error,
+                    };
+                    continue;
+                }
+            };
+            manifest_entry.filename = dest
+                .file_name()
+                .map(|n| n.to_string_lossy().into_owned())
+                .unwrap_or_default();
+            manifest_entry.placed = pending.partial.is_some();
+            if execute && pending.remove_source {
+                manifest_entry.removal = Some(SourceRemoval::Moved(pending.source));
+            }
+
+            // Later duplicates in the run resolve to this file, and later
+            // copies of a quarantined file are recognised
+            match &*result {
+                FileProcessingResult::Imported { manifest_entry: Some(entry) }
+                | FileProcessingResult::Undated { manifest_entry: Some(entry) } => {
+                    self.dedup_index.lock().unwrap().insert(
+                        entry.entry.hash_algorithm,
+                        entry.entry.hash.clone(),
+                        dest,
+                        entry.entry.file_size_bytes,
+                    );
+                }
+                FileProcessingResult::Corrupt { manifest_entry: Some(entry), .. } if !entry.entry.hash.is_empty() => {
+                    self.dedup_index.lock().unwrap().insert_quarantined(
+                        entry.entry.hash_algorithm,
+                        entry.entry.hash.clone(),
+                        dest,
+                        entry.entry.file_size_bytes,
+                    );
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Add a placed batch to its folders' manifests and save them, then
+    /// remove the sources of entries that were saved. Where a manifest
+    /// cannot be saved, the batch's files there are taken out again and
+    /// dropped from the run like files never started, so the library never
+    /// holds a file its manifest does not list and their sources stay.
+    /// Returns the folders whose manifest was saved and those whose was not.
+    fn record_batch(
+        &self,
+        sources: &mut Vec<PathBuf>,
+        results: &mut Vec<FileProcessingResult>,
+        manifests: &mut HashMap<PathBuf, manifest::Manifest>,
+    ) -> (Vec<PathBuf>, Vec<PathBuf>) {
+        let mut touched = std::collections::BTreeSet::new();
+        for entry in results.iter().filter_map(|r| r.manifest_entry()) {
+            manifests
+                .entry(entry.dir.clone())
+                .or_insert_with(|| manifest::load_manifest(&entry.dir, &|message| self.observer.warning(message)))
+                .files
+                .insert(entry.filename.clone(), entry.entry.clone());
+            touched.insert(entry.dir.clone());
+        }
+
+        let mut saved = Vec::new();
+        let mut failed = std::collections::BTreeSet::new();
+        for dir in touched {
+            let m = &manifests[&dir];
+            match manifest::save_manifest(&dir, m) {
+                Ok(()) => saved.push(dir),
+                Err(e) => {
+                    if is_disk_full(&e) {
+                        self.disk_full.store(true, Ordering::Relaxed);
+                    }
+                    self.unrecorded.store(true, Ordering::Relaxed);
+                    self.observer.warning(&format!(
+                        "Failed to save manifest in {}: {}; its new files were taken out and their sources kept",
+                        dir.display(),
+                        e
+                    ));
+                    failed.insert(dir);
+                }
+            }
+        }
+
+        let recorded: Vec<bool> = results
+            .iter()
+            .map(|r| r.manifest_entry().is_none_or(|entry| !failed.contains(&entry.dir)))
+            .collect();
+        for (result, _) in results.iter().zip(&recorded).filter(|(_, recorded)| !**recorded) {
+            let Some(entry) = result.manifest_entry() else {
+                continue;
+            };
+            if entry.placed {
+                std::fs::remove_file(entry.dir.join(&entry.filename)).ok();
+            }
+            if let Some(m) = manifests.get_mut(&entry.dir) {
+                m.files.remove(&entry.filename);
+            }
+        }
+        let mut keep = recorded.iter();
+        sources.retain(|_| *keep.next().unwrap_or(&true));
+        let mut keep = recorded.iter();
+        results.retain(|_| *keep.next().unwrap_or(&true));
+
+        for result in results.iter_mut() {
+            let Some(entry) = result.manifest_entry_mut() else {
+                continue;
+            };
+            entry.source_removed = match entry.removal.take() {
+                Some(SourceRemoval::Moved(source)) => self.remove_source_safely(&source, &entry.dir.join(&entry.filename)),
+                Some(SourceRemoval::Duplicate {
+                    source,
+                    canonical,
+                    algorithm,
+                    hash,
+                    fingerprint,
+                }) => self.remove_source_if_canonical(&source, fingerprint, &canonical, algorithm, &hash),
+                None => false,
+            };
+        }
+        (saved, failed.into_iter().collect())
+    }
+
+    /// Describe the outcome for one recognised source file.
+    fn file_event(&self, path: &Path, result: &FileProcessingResult) -> FileEvent {
+        let entry = result.manifest_entry();
+        let (category, duplicate_of, error, hash) = match result {
+            FileProcessingResult::Imported { .. } => (events::Category::Imported, None, None, None),
+            FileProcessingResult::Undated { .. } => (events::Category::Undated, None, None, None),
+            FileProcessingResult::Duplicate { canonical, hash, .. } => (
+                events::Category::Duplicate,
+                Some(canonical.display().to_string()),
+                None,
+                Some(hash.clone()),
+            ),
+            FileProcessingResult::Corrupt { error, .. } => (events::Category::Corrupt, None, Some(error.clone()), None),
+        };
+        // Record and delete-source write a manifest entry but no file
+        let destination = entry
+            .filter(|_| category != events::Category::Duplicate || self.importer.duplicates == DuplicatePolicy::Copy)
+            .map(|e| e.dir.join(&e.filename).display().to_string());
+        let hash = entry
+            .map(|e| e.entry.hash.clone())
+            .filter(|h| !h.is_empty())
+            .or(hash);
+        FileEvent {
+            source: path.display().to_string(),
+            category,
+            destination,
+            hash_algorithm: hash.as_ref().map(|_| self.algorithm().as_str()),
+            hash,
+            date: entry.and_then(|e| e.captured.clone()),
+            date_source: entry.and_then(|e| e.entry.date_source.clone()),
+            duplicate_of,
+            error,
+            source_removed: entry.is_some_and(|e| e.source_removed),
+            dry_run: !self.importer.execute,
+        }
+    }
+
+    /// Copy `source` into `dest_dir` under a temporary name. Buffered
+    /// contents are written straight out with the hash the caller computed
+    /// from those same bytes; otherwise
+    /// the file is streamed from disk and hashed on the way. The partial
+    /// file is removed if the copy fails, and a full target stops the run.
+    fn copy_to_partial(
+        &self,
+        source: &Path,
+        buffered: Option<(&[u8], [u8; 32])>,
+        dest_dir: &Path,
+    ) -> std::io::Result<(PathBuf, [u8; 32])> {
+        let algorithm = self.algorithm();
+        let throttle = self.throttle.as_ref();
+        let partial = dest_dir.join(format!(
+            ".{}-{}.partial",
+            std::process::id(),
+            PARTIAL_SEQ.fetch_add(1, Ordering::Relaxed)
+        ));
+        let copied = std::fs::create_dir_all(dest_dir).and_then(|()| {
+            if self.swept_dirs.lock().unwrap().insert(dest_dir.to_path_buf()) {
+                remove_stale_partials(dest_dir);
+            }
+            match buffered {
+                Some((bytes, hash)) => throttle::write_file(&partial, bytes, throttle).map(|()| hash),
+                None => metadata::copy_and_hash(source, &partial, &[algorithm], throttle)
+                    .map(|digests| digests.get(algorithm).unwrap_or_default()),
+            }
+            .and_then(|hash| self.take_simulated_space(&partial).map(|()| hash))
+        });
+        match copied {
+            Ok(hash) => Ok((partial, hash)),
+            Err(e) => {
+                std::fs::remove_file(&partial).ok();
+                if is_disk_full(&e) && !self.disk_full.swap(true, Ordering::Relaxed) {
+                    self.observer.warning("Target disk full, stopping after files in progress");
+                }
+                Err(e)
+            }
+        }
+    }
+
+    /// Charge a written file against the simulated free space, failing with
+    /// `ENOSPC` once it runs out.
+    fn take_simulated_space(&self, written: &Path) -> std::io::Result<()> {
+        let Some(free_space) = &self.free_space else {
+            return Ok(());
+        };
+        let size = written.metadata()?.len();
+        free_space
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |free| free.checked_sub(size))
+            .map(|_| ())
+            .map_err(|_| std::io::Error::from_raw_os_error(libc::ENOSPC))
+    }
+
+    /// Remove a moved source once its copy is in place. Returns whether it
+    /// was removed.
+    fn remove_source_safely(&self, source: &Path, dest: &Path) -> bool {
+        match (dest.exists(), dest.metadata()) {
+            (true, Ok(dest_meta)) => match source.metadata() {
+                Ok(src_meta) if dest_meta.len() == src_meta.len() => match std::fs::remove_file(source) {
+                    Ok(()) => true,
+                    Err(e) => {
+                        self.observer.warning(&format!("Failed to remove source {}: {}", source.display(), e));
+                        false
+                    }
+                },
+                _ => {
+                    self.observer.warning(&format!("Size mismatch after copy, source preserved: {}", source.display()));
+                    false
+                }
+            },
+            _ => {
+                self.observer.warning(&format!("Dest verification failed, source preserved: {}", source.display()));
+                false
+            }
+        }
+    }
+
+    /// Remove a duplicate source only if it is unchanged since it was
+    /// hashed (`fingerprint`), and after re-hashing the canonical library
+    /// file with the algorithm it was recorded under and confirming it still
+    /// holds the same content.
+    fn remove_source_if_canonical(
+        &self,
+        source: &Path,
+        fingerprint: Option<cache::Fingerprint>,
+        canonical: &Path,
+        algorithm: HashAlgorithm,
+        hash: &[u8; 32],
+    ) -> bool {
+        if fingerprint.is_none() || cache::fingerprint(source) != fingerprint {
+            self.observer.warning(&format!("Source changed since it was hashed, source preserved: {}", source.display()));
+            return false;
+        }
+        match hash_one(canonical, algorithm) {
+            Ok(canonical_hash) if &canonical_hash == hash => match std::fs::remove_file(source) {
+                Ok(()) => true,
+                Err(e) => {
+                    self.observer.warning(&format!("Failed to remove source {}: {}", source.display(), e));
+                    false
+                }
+            },
+            Ok(_) => {
+                self.observer.warning(&format!(
+                    "Canonical file {} no longer matches, source preserved: {}",
+                    canonical.display(),
+                    source.display()
+                ));
+                false
+            }
+            Err(e) => {
+                self.observer.warning(&format!(
+                    "Cannot verify canonical file {} ({}), source preserved: {}",
+                    canonical.display(),
+                    e,
+                    source.display()
+                ));
+                false
+            }
+        }
+    }
+}
+
+static PARTIAL_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// Files copied before a batch is named and placed. Bounds the partial
+/// copies and unremoved `--move` sources a crash can leave behind.
+const PLACE_BATCH: usize = 128;
+
+/// Remove `.{pid}-{seq}.partial` files in `dir` left by earlier imports.
+/// Only runs that hold the target lock write here, so any partial another
+/// process wrote, on this host or another sharing the target, belongs to a
+/// run that has stopped.
+fn remove_stale_partials(dir: &Path) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let Some(pid) = name
+            .to_str()
+            .and_then(|name| name.strip_prefix('.')?.strip_suffix(".partial"))
+            .and_then(|stem| stem.split_once('-'))
+            .and_then(|(pid, seq)| seq.parse::<usize>().ok().and(pid.parse::<u32>().ok()))
+        else {
+            continue;
+        };
+        if pid != std::process::id() {
+            std::fs::remove_file(entry.path()).ok();
+        }
+    }
+}
+
+fn is_disk_full(err: &std::io::Error) -> bool {
+    err.raw_os_error() == Some(28)
+}
@@ -0,0 +1,342 @@
+use crate::manifest::{self, DedupIndex, Manifest};
+use crate::metadata::HashAlgorithm;
+use rusqlite::{Connection, OpenFlags, params};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+const INDEX_FILE: &str = ".library.db";
+
+/// Bumped whenever the table layout changes. An index with another version is
+/// dropped and rebuilt from the manifests on the next `refresh`.
+const SCHEMA_VERSION: i32 = 3;
+
+/// Derived SQLite index of every manifest in a library, so startup does not
+/// have to parse each `.manifest.json`. Manifests stay the source of truth:
+/// each indexed manifest's mtime and size are remembered, and any manifest
+/// that changed, appeared or vanished since is re-read on `refresh`.
+///
+/// Finding those manifests does not list the whole library either. The
+/// mtime of every directory is remembered too; a directory whose mtime is
+/// unchanged has the same entries as before, so only its manifest, if it
+/// had one, is checked again. Checking freshness costs a `stat` or two per
+/// directory rather than reading every directory's file listing.
+pub struct LibraryIndex {
+    conn: Connection,
+    target: PathBuf,
+}
+
+/// A library file and the `duplicates/` entries that point at it.
+#[derive(Debug)]
+pub struct DuplicateCluster {
+    /// Library file, relative to the target
+    pub canonical: String,
+    /// Manifest entries in `duplicates/`, relative to the target, with the
+    /// source path each was imported from
+    pub duplicates: Vec<(String, String)>,
+}
+
+/// Directories and manifests found by `LibraryIndex::scan`, keyed by
+/// directory relative to the target.
+#[derive(Default)]
+struct Scan {
+    dirs: HashMap<String, i64>,
+    manifests: HashMap<String, Stamp>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy)]
+struct Stamp {
+    mtime_ns: i64,
+    size: i64,
+}
+
+impl LibraryIndex {
+    /// Open the index, creating it if needed. `target` must exist.
+    pub fn open(target: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(target.join(INDEX_FILE))?;
+        let version: i32 = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            conn.execute_batch("DROP TABLE IF EXISTS files; DROP TABLE IF EXISTS manifests; DROP TABLE IF EXISTS dirs;")?;
+            conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+        }
+        conn.execute_batch(
+            "CREATE TABLE IF NOT EXISTS manifests (
+                 dir TEXT PRIMARY KEY,
+                 mtime_ns INTEGER NOT NULL,
+                 size INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS dirs (
+                 dir TEXT PRIMARY KEY,
+                 mtime_ns INTEGER NOT NULL
+             );
+             CREATE TABLE IF NOT EXISTS files (
+                 dir TEXT NOT NULL,
+                 filename TEXT NOT NULL,
+                 hash TEXT NOT NULL,
+                 hash_algorithm TEXT NOT NULL,
+                 file_size_bytes INTEGER NOT NULL,
+                 quarantine INTEGER NOT NULL,
+                 original_path TEXT NOT NULL,
+                 date_source TEXT,
+                 duplicate_of TEXT,
+                 PRIMARY KEY (dir, filename)
+             );
+             CREATE INDEX IF NOT EXISTS files_hash ON files (hash);",
+        )?;
+        Ok(LibraryIndex {
+            conn,
+            target: target.to_path_buf(),
+        })
+    }
+
+    /// Open an existing index without creating or modifying anything.
+    pub fn open_read_only(target: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open_with_flags(
+            target.join(INDEX_FILE),
+            OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX,
+        )?;
+        Ok(LibraryIndex {
+            conn,
+            target: target.to_path_buf(),
+        })
+    }
+
+    /// Whether every manifest on disk is indexed as it currently stands, in
+    /// the current schema.
+    pub fn is_fresh(&self) -> rusqlite::Result<bool> {
+        let version: i32 = self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if version != SCHEMA_VERSION {
+            return Ok(false);
+        }
+        let indexed = self.indexed_stamps()?;
+        Ok(indexed == self.scan(&indexed)?.manifests)
+    }
+
+    /// Re-read manifests that changed since they were indexed and drop
+    /// those that no longer exist. Returns how many manifests were re-read;
+    /// corrupt ones are reported through `warn` and indexed as empty.
+    pub fn refresh(&mut self, warn: &dyn Fn(&str)) -> rusqlite::Result<usize> {
+        let indexed = self.indexed_stamps()?;
+        let on_disk = self.scan(&indexed)?;
+
+        let tx = self.conn.transaction()?;
+        for dir in indexed.keys().filter(|dir| !on_disk.manifests.contains_key(*dir)) {
+            tx.execute("DELETE FROM files WHERE dir = ?1", params![dir])?;
+            tx.execute("DELETE FROM manifests WHERE dir = ?1", params![dir])?;
+        }
+        let mut reread = 0;
+        for (dir, stamp) in &on_disk.manifests {
+            if indexed.get(dir) == Some(stamp) {
+                continue;
+            }
+            let manifest = manifest::load_manifest(&self.target.join(dir), warn);
+            replace_manifest(&tx, dir, *stamp, &manifest)?;
+            reread += 1;
+        }
+        tx.execute("DELETE FROM dirs", [])?;
+        for (dir, mtime_ns) in &on_disk.dirs {
+            tx.execute("INSERT INTO dirs (dir, mtime_ns) VALUES (?1, ?2)", params![dir, mtime_ns])?;
+        }
+        tx.commit()?;
+        Ok(reread)
+    }
+
+    /// Index manifests that were just written, in a single transaction.
+    pub fn record_manifests(&mut self, saved: &[(PathBuf, &Manifest)]) -> rusqlite::Result<()> {
+        let tx = self.conn.transaction()?;
+        for (dir, manifest) in saved {
+            let (Some(rel), Some(stamp)) = (relative_dir(&self.target, dir), stamp_of(dir)) else {
+                continue;
+            };
+            replace_manifest(&tx, &rel, stamp, manifest)?;
+            // Saving replaced the manifest, which changed the directory
+            if let Some(mtime_ns) = mtime_of(dir) {
+                tx.execute(
+                    "INSERT OR REPLACE INTO dirs (dir, mtime_ns) VALUES (?1, ?2)",
+                    params![rel, mtime_ns],
+                )?;
+            }
+        }
+        tx.commit()
+    }
+
+    /// Build the in-memory dedup index. Library rows are loaded first so
+    /// duplicate references only fill gaps.
+    pub fn dedup_index(&self) -> rusqlite::Result<DedupIndex> {
+        let mut index = DedupIndex::default();
+        let mut stmt = self.conn.prepare(
+            "SELECT dir, filename, hash, hash_algorithm, file_size_bytes, duplicate_of FROM files
+             WHERE hash != '' ORDER BY quarantine",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+                row.get::<_, i64>(4)?,
+                row.get::<_, Option<String>>(5)?,
+            ))
+        })?;
+        for row in rows {
+            let (dir, filename, hash, algorithm, size, duplicate_of) = row?;
+            let Some(algorithm) = HashAlgorithm::from_name(&algorithm) else {
+                continue;
+            };
+            index.add(&self.target, &dir, &filename, (algorithm, hash), size as u64, duplicate_of.as_deref());
+        }
+        Ok(index)
+    }
+
+    /// Every library file that has entries in `duplicates/`, ordered by
+    /// path.
+    pub fn duplicate_clusters(&self) -> rusqlite::Result<Vec<DuplicateCluster>> {
+        let mut stmt = self.conn.prepare(
+            "SELECT duplicate_of, dir, filename, original_path FROM files
+             WHERE dir = 'duplicates' AND duplicate_of IS NOT NULL
+             ORDER BY duplicate_of, filename",
+        )?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, String>(3)?,
+            ))
+        })?;
+        let mut clusters: Vec<DuplicateCluster> = Vec::new();
+        for row in rows {
+            let (canonical, dir, filename, original_path) = row?;
+            let duplicate = (format!("{}/{}", dir, filename), original_path);
+            match clusters.last_mut() {
+                Some(cluster) if cluster.canonical == canonical => cluster.duplicates.push(duplicate),
+                _ => clusters.push(DuplicateCluster {
+                    canonical,
+                    duplicates: vec![duplicate],
+                }),
+            }
+        }
+        Ok(clusters)
+    }
+
+    /// Find every manifest under the target, listing only directories
+    /// whose mtime changed since the last `refresh`. In the others, only
+    /// manifests in `indexed` can exist.
+    fn scan(&self, indexed: &HashMap<String, Stamp>) -> rusqlite::Result<Scan> {
+        let known: HashMap<String, i64> = {
+            let mut stmt = self.conn.prepare("SELECT dir, mtime_ns FROM dirs")?;
+            let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        };
+        let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+        for dir in known.keys().filter(|dir| !dir.is_empty()) {
+            let parent = dir.rsplit_once('/').map_or("", |(parent, _)| parent);
+            children.entry(parent).or_default().push(dir);
+        }
+
+        let mut scan = Scan::default();
+        let mut pending = vec![String::new()];
+        while let Some(dir) = pending.pop() {
+            let path = self.target.join(&dir);
+            let Some(mtime_ns) = mtime_of(&path) else {
+                continue;
+            };
+            if known.get(&dir) == Some(&mtime_ns) {
+                pending.extend(children.get(dir.as_str()).into_iter().flatten().map(|c| c.to_string()));
+                // Unchanged entries, but the manifest may have been
+                // rewritten in place
+                if indexed.contains_key(&dir)
+                    && let Some(stamp) = stamp_of(&path)
+                {
+                    scan.manifests.insert(dir.clone(), stamp);
+                }
+            } else {
+                let Ok(entries) = std::fs::read_dir(&path) else {
+                    continue;
+                };
+                for entry in entries.flatten() {
+                    let name = entry.file_name().to_string_lossy().into_owned();
+                    if !name.starts_with('.') && entry.file_type().is_ok_and(|t| t.is_dir()) {
+                        pending.push(if dir.is_empty() { name } else { format!("{}/{}", dir, name) });
+                    }
+                }
+                if let Some(stamp) = stamp_of(&path) {
+                    scan.manifests.insert(dir.clone(), stamp);
+                }
+            }
+            scan.dirs.insert(dir, mtime_ns);
+        }
+        Ok(scan)
+    }
+
+    fn indexed_stamps(&self) -> rusqlite::Result<HashMap<String, Stamp>> {
+        let mut stmt = self.conn.prepare("SELECT dir, mtime_ns, size FROM manifests")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                Stamp {
+                    mtime_ns: row.get(1)?,
+                    size: row.get(2)?,
+                },
+            ))
+        })?;
+        rows.collect()
+    }
+}
+
+fn replace_manifest(
+    tx: &rusqlite::Transaction,
+    dir: &str,
+    stamp: Stamp,
+    manifest: &Manifest,
+) -> rusqlite::Result<()> {
+    let quarantine = manifest::is_quarantined(dir);
+    tx.execute("DELETE FROM files WHERE dir = ?1", params![dir])?;
+    let mut insert = tx.prepare_cached(
+        "INSERT INTO files (dir, filename, hash, hash_algorithm, file_size_bytes, quarantine, original_path, date_source, duplicate_of)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+    )?;
+    for (filename, entry) in &manifest.files {
+        insert.execute(params![
+            dir,
+            filename,
+            entry.hash,
+            entry.hash_algorithm.as_str(),
+            entry.file_size_bytes as i64,
+            quarantine,
+            entry.original_path,
+            entry.date_source,
+            entry.duplicate_of,
+        ])?;
+    }
+    tx.execute(
+        "INSERT OR REPLACE INTO manifests (dir, mtime_ns, size) VALUES (?1, ?2, ?3)",
+        params![dir, stamp.mtime_ns, stamp.size],
+    )?;
+    Ok(())
+}
+
+fn relative_dir(target: &Path, dir: &Path) -> Option<String> {
+    dir.strip_prefix(target)
+        .ok()
+        .map(|rel| rel.to_string_lossy().into_owned())
+}
+
+fn mtime_of(path: &Path) -> Option<i64> {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some(
+        metadata
+            .modified()
+            .ok()?
+            .duration_since(std::time::UNIX_EPOCH)
+            .ok()?
+            .as_nanos() as i64,
+    )
+}
+
+fn stamp_of(dir: &Path) -> Option<Stamp> {
+    let path = dir.join(".manifest.json");
+    Some(Stamp {
+        mtime_ns: mtime_of(&path)?,
+        size: std::fs::metadata(&path).ok()?.len() as i64,
+    })
+}
@@ -1,3 +1,15 @@
+pub mod buffer;
+pub mod cache;
+pub mod concurrency;
+pub mod config;
+pub mod events;
+pub mod import;
+pub mod index;
+pub mod library;
 pub mod manifest;
 pub mod metadata;
+pub mod report;
 pub mod scan;
+pub mod space;
+pub mod throttle;
+pub mod validate;
@@ -0,0 +1,396 @@
+use crate::config::{Config, ConfigError, DateParts, Template};
+use crate::manifest::{self, Manifest};
+use crate::metadata;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+const LIBRARY_FILE: &str = ".library.toml";
+
+const LOCK_FILE: &str = ".import.lock";
+
+const LIBRARY_VERSION: u32 = 1;
+
+/// Top-level folders the importer fills itself, whatever the layout.
+const RESERVED_DIRS: [&str; 3] = ["undated", "duplicates", "corrupt"];
+
+/// Settings a library is bound to on its first import. Every later import
+/// must use the same ones; `migrate` moves existing files to new ones.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LibrarySettings {
+    pub version: u32,
+    pub layout: Template,
+    pub naming: Template,
+}
+
+impl LibrarySettings {
+    pub fn new(layout: Template, naming: Template) -> Self {
+        LibrarySettings {
+            version: LIBRARY_VERSION,
+            layout,
+            naming,
+        }
+    }
+}
+
+pub fn settings_path(target: &Path) -> PathBuf {
+    target.join(LIBRARY_FILE)
+}
+
+/// The settings stored in `target`, or `None` for a library that has not
+/// been bound yet.
+pub fn load_settings(target: &Path) -> Result<Option<LibrarySettings>, ConfigError> {
+    let path = settings_path(target);
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            return Err(ConfigError {
+                path,
+                message: e.to_string(),
+            });
+        }
+    };
+    let settings: LibrarySettings = toml::from_str(&content).map_err(|e| ConfigError {
+        path: path.clone(),
+        message: e.to_string(),
+    })?;
+    if settings.version != LIBRARY_VERSION {
+        return Err(ConfigError {
+            path,
+            message: format!("unsupported library version {}", settings.version),
+        });
+    }
+    Ok(Some(settings))
+}
+
+/// The settings `target` is bound to. A library imported into before
+/// settings were recorded has manifests but no `.library.toml`; it was laid
+/// out with the defaults, so it is bound to those. `None` only for a target
+/// that holds no library yet.
+pub fn bound_settings(target: &Path) -> Result<Option<LibrarySettings>, ConfigError> {
+    if let Some(settings) = load_settings(target)? {
+        return Ok(Some(settings));
+    }
+    let has_manifests = WalkDir::new(target)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|e| e.ok())
+        .any(|e| e.file_type().is_dir() && e.path().join(".manifest.json").is_file());
+    let defaults = Config::default();
+    Ok(has_manifests.then(|| LibrarySettings::new(defaults.layout, defaults.naming)))
+}
+
+pub fn save_settings(target: &Path, settings: &LibrarySettings) -> std::io::Result<()> {
+    let toml = toml::to_string(settings).map_err(std::io::Error::other)?;
+    std::fs::create_dir_all(target)?;
+    std::fs::write(
+        settings_path(target),
+        format!("# Bound on first import. Change with `image-organiser library migrate`.\n{}", toml),
+    )
+}
+
+/// Exclusive hold on a target, so one `--execute` import or migration
+/// writes into it at a time. Released when dropped.
+pub struct TargetLock {
+    _file: std::fs::File,
+}
+
+/// Lock `target` through `<TARGET>/.import.lock`, creating it if needed.
+/// Fails at once with `ErrorKind::WouldBlock` when another process holds
+/// the lock.
+pub fn lock_target(target: &Path) -> std::io::Result<TargetLock> {
+    std::fs::create_dir_all(target)?;
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(target.join(LOCK_FILE))?;
+    match file.try_lock() {
+        Ok(()) => Ok(TargetLock { _file: file }),
+        Err(std::fs::TryLockError::WouldBlock) => Err(std::io::Error::new(
+            std::io::ErrorKind::WouldBlock,
+            format!("{} is in use by another import or migration", target.display()),
+        )),
+        Err(std::fs::TryLockError::Error(e)) => Err(e),
+    }
+}
+
+/// Outcome of `migrate`.
+#[derive(Debug, Default)]
+pub struct Migration {
+    /// Library files moved, or that would be in a dry run
+    pub moved: Vec<(PathBuf, PathBuf)>,
+    /// Dated files whose capture time could not be read back from their
+    /// path; they stay where they are
+    pub stranded: Vec<PathBuf>,
+}
+
+/// Move every dated library file from the `from` layout and naming to `to`,
+/// update the manifests and duplicate references to match, and bind the
+/// library to `to`. Capture times are read back from the current paths;
+/// parts the old templates did not record default to the first of the
+/// month and midnight. Dry runs only report the moves.
+///
+/// Folders are migrated one at a time: each folder's files are moved and
+/// the manifests they touch saved before the next folder starts. If a move
+/// or save fails, that folder is put back as it was and the error returned;
+/// folders already done keep the new layout. The library stays bound to
+/// `from` until every folder is done, and running `migrate` again skips
+/// files already laid out as `to`. Executed migrations hold the target
+/// lock. Corrupt manifests are reported through `warn` and read as empty.
+pub fn migrate(
+    target: &Path,
+    from: &LibrarySettings,
+    to: &LibrarySettings,
+    execute: bool,
+    warn: &dyn Fn(&str),
+) -> std::io::Result<Migration> {
+    let _lock = execute.then(|| lock_target(target)).transpose()?;
+    let mut manifests: BTreeMap<PathBuf, Manifest> = BTreeMap::new();
+    for entry in WalkDir::new(target)
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir())
+    {
+        if entry.path().join(".manifest.json").is_file() {
+            manifests.insert(entry.path().to_path_buf(), manifest::load_manifest(entry.path(), warn));
+        }
+    }
+
+    let names = manifest::NameAllocator::new(execute);
+    let mut migration = Migration::default();
+    let dirs: Vec<PathBuf> = manifests.keys().cloned().collect();
+    for dir in dirs {
+        let Ok(relative_dir) = dir.strip_prefix(target) else {
+            continue;
+        };
+        let top = relative_dir.components().next().map(|c| c.as_os_str().to_string_lossy());
+        if top.is_some_and(|top| RESERVED_DIRS.contains(&top.as_ref())) {
+            continue;
+        }
+        let relative_dir = relative_dir.to_string_lossy().replace('\\', "/");
+        let mut moves = Vec::new();
+        for (filename, file_entry) in &manifests[&dir].files {
+            let path = dir.join(filename);
+            let (stem, extension) = match filename.rsplit_once('.') {
+                Some((stem, extension)) => (stem, extension),
+                None => (filename.as_str(), ""),
+            };
+            let hash = metadata::parse_hash(&file_entry.hash).unwrap_or_default();
+            let placement = |settings: &LibrarySettings| {
+                let parts = read_parts(settings, &relative_dir, stem)?;
+                let new_dir = target.join(to.layout.render_parts(parts));
+                let candidates = manifest::filename_candidates(&to.naming.render_parts(parts), extension, &hash);
+                Some((new_dir, candidates))
+            };
+            // Already moved by an earlier, interrupted migration
+            if placement(to).is_some_and(|(new_dir, candidates)| new_dir == dir && candidates.contains(filename)) {
+                continue;
+            }
+            let Some((new_dir, candidates)) = placement(from) else {
+                migration.stranded.push(path);
+                continue;
+            };
+            if new_dir == dir && candidates.contains(filename) {
+                continue;
+            }
+            let dest = match names.allocate(&new_dir, candidates) {
+                Ok(dest) => dest,
+                Err(e) => {
+                    remove_placeholders(target, &moves);
+                    return Err(e);
+                }
+            };
+            moves.push((filename.clone(), path, new_dir, dest));
+        }
+        if execute && !moves.is_empty() {
+            migrate_dir(target, &dir, &moves, &mut manifests, warn)?;
+        }
+        migration
+            .moved
+            .extend(moves.into_iter().map(|(_, path, _, dest)| (path, dest)));
+    }
+    if execute {
+        save_settings(target, to)?;
+    }
+    Ok(migration)
+}
+
+/// Move one folder's files to their new places and save every manifest
+/// that changes: the destinations first, then duplicate references, then
+/// the folder's own, so an interruption leaves an entry listed twice rather
+/// than a file listed nowhere. On failure the files are moved back, the
+/// placeholders of files not yet moved removed and the manifests already
+/// saved restored.
+fn migrate_dir(
+    target: &Path,
+    dir: &Path,
+    moves: &[(String, PathBuf, PathBuf, PathBuf)],
+    manifests: &mut BTreeMap<PathBuf, Manifest>,
+    warn: &dyn Fn(&str),
+) -> std::io::Result<()> {
+    let mut moved = Vec::new();
+    for (_, path, _, dest) in moves {
+        if let Err(e) = std::fs::rename(path, dest) {
+            remove_placeholders(target, &moves[moved.len()..]);
+            undo_moves(target, &moved);
+            return Err(e);
+        }
+        moved.push((path.clone(), dest.clone()));
+    }
+
+    // Manifests as they were, to restore after a failed save
+    let mut before: BTreeMap<PathBuf, Manifest> = BTreeMap::new();
+    let mut snapshot = |manifests: &mut BTreeMap<PathBuf, Manifest>, dir: &Path| {
+        let m = manifests
+            .entry(dir.to_path_buf())
+            .or_insert_with(|| manifest::load_manifest(dir, warn));
+        before.entry(dir.to_path_buf()).or_insert_with(|| m.clone());
+    };
+    let mut arrivals = Vec::new();
+    let mut renamed: HashMap<String, String> = HashMap::new();
+    for (filename, path, new_dir, dest) in moves {
+        snapshot(manifests, dir);
+        snapshot(manifests, new_dir);
+        let Some(file_entry) = manifests.get_mut(dir).and_then(|m| m.files.remove(filename)) else {
+            continue;
+        };
+        let new_name = dest
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if let Some(m) = manifests.get_mut(new_dir) {
+            m.files.insert(new_name, file_entry);
+        }
+        renamed.insert(relative(target, path), relative(target, dest));
+        if !arrivals.contains(new_dir) {
+            arrivals.push(new_dir.clone());
+        }
+    }
+    let mut referencing = Vec::new();
+    for (other, m) in manifests.iter_mut() {
+        let stale: Vec<String> = m
+            .files
+            .iter()
+            .filter(|(_, e)| e.duplicate_of.as_ref().is_some_and(|old| renamed.contains_key(old)))
+            .map(|(name, _)| name.clone())
+            .collect();
+        if stale.is_empty() {
+            continue;
+        }
+        before.entry(other.clone()).or_insert_with(|| m.clone());
+        for name in stale {
+            if let Some(e) = m.files.get_mut(&name) {
+                e.duplicate_of = e.duplicate_of.as_ref().and_then(|old| renamed.get(old)).cloned();
+            }
+        }
+        referencing.push(other.clone());
+    }
+
+    let mut order = arrivals;
+    for changed in referencing.into_iter().chain(std::iter::once(dir.to_path_buf())) {
+        if !order.contains(&changed) {
+            order.push(changed);
+        }
+    }
+    let mut saved = Vec::new();
+    for changed in &order {
+        let result = match manifests.get(changed) {
+            Some(m) if m.files.is_empty() => remove_manifest(target, changed),
+            Some(m) => manifest::save_manifest(changed, m),
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            undo_moves(target, &moved);
+            for (restored, m) in before {
+                if saved.contains(&restored) {
+                    if m.files.is_empty() {
+                        remove_manifest(target, &restored).ok();
+                    } else {
+                        manifest::save_manifest(&restored, &m).ok();
+                    }
+                }
+                manifests.insert(restored, m);
+            }
+            return Err(e);
+        }
+        saved.push(changed.clone());
+    }
+    Ok(())
+}
+
+/// Move files back to where they were, newest move first, and drop the
+/// folders that were created for them.
+fn undo_moves(target: &Path, moved: &[(PathBuf, PathBuf)]) {
+    for (path, dest) in moved.iter().rev() {
+        std::fs::rename(dest, path).ok();
+        remove_empty_parents(target, dest);
+    }
+}
+
+/// Remove the empty placeholders holding the destinations of moves that
+/// were not made, and the folders created for them.
+fn remove_placeholders(target: &Path, moves: &[(String, PathBuf, PathBuf, PathBuf)]) {
+    for (_, _, _, dest) in moves.iter().rev() {
+        std::fs::remove_file(dest).ok();
+        remove_empty_parents(target, dest);
+    }
+}
+
+/// Remove the folders above `path` that are left empty, up to `target`.
+fn remove_empty_parents(target: &Path, path: &Path) {
+    for empty in path.ancestors().skip(1).take_while(|d| *d != target) {
+        if std::fs::remove_dir(empty).is_err() {
+            break;
+        }
+    }
+}
+
+/// Remove an emptied folder's manifest, and the folder and any parents the
+/// old layout no longer needs.
+fn remove_manifest(target: &Path, dir: &Path) -> std::io::Result<()> {
+    match std::fs::remove_file(dir.join(".manifest.json")) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    for empty in dir.ancestors().take_while(|d| *d != target) {
+        if std::fs::remove_dir(empty).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Capture time recorded in a dated file's folder and name under `settings`.
+/// The name may carry a hash suffix added to resolve a collision.
+fn read_parts(settings: &LibrarySettings, relative_dir: &str, stem: &str) -> Option<DateParts> {
+    let from_dir = settings.layout.parse(relative_dir)?;
+    let from_name = settings.naming.parse(stem).or_else(|| {
+        let (base, suffix) = stem.rsplit_once('_')?;
+        let is_suffix = matches!(suffix.len(), 4 | 8) && suffix.bytes().all(|b| b.is_ascii_hexdigit());
+        is_suffix.then(|| settings.naming.parse(base)).flatten()
+    })?;
+    let mut parts = [0; 6];
+    for (i, part) in parts.iter_mut().enumerate() {
+        *part = match (from_dir[i], from_name[i]) {
+            (Some(a), Some(b)) if a != b => return None,
+            (Some(value), _) | (None, Some(value)) => value,
+            // A year is required; the rest default to the start of the period
+            (None, None) if i == 0 => return None,
+            (None, None) if i < 3 => 1,
+            (None, None) => 0,
+        };
+    }
+    Some(parts)
+}
+
+fn relative(target: &Path, path: &Path) -> String {
+    path.strip_prefix(target)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
@@ -1,13 +1,15 @@
-mod manifest;
-mod metadata;
-mod scan;
-
-use clap::{Parser, Subcommand};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use image_organiser::concurrency::Jobs;
+use image_organiser::config::{Config, Template};
+use image_organiser::events::{self, Category, FileEvent, SummaryEvent};
+use image_organiser::import::{self, DuplicatePolicy, ImportError, Importer, Observer, Phase, Stopped};
+use image_organiser::metadata::{BuiltinExtractor, DateChain, HashAlgorithm};
+use image_organiser::library::{self, LibrarySettings};
+use image_organiser::{cache, index, report, throttle};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::path::{Path, PathBuf};
-use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
-use rayon::prelude::*;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[derive(Parser)]
 #[command(name = "image-organiser")]
@@ -20,6 +22,8 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
     /// Import media files from source into organized target
+    #[command(after_help = "Exit status: 0 clean, 1 could not run, 2 usage error, \
+3 completed with corrupt or undated files, 4 target disk full, 130 interrupted")]
     Import {
         /// Source directory to scan
         source: PathBuf,
@@ -34,327 +38,217 @@ enum Commands {
         /// Suppress per-file output (show only progress bar and summary)
         #[arg(long, short)]
         quiet: bool,
+        /// What to do with files already present in the library [default: copy]
+        #[arg(long, value_enum)]
+        duplicates: Option<DuplicatePolicy>,
+        /// Ignore and do not update the source hash cache in the target
+        #[arg(long)]
+        no_cache: bool,
+        /// Content hash for new manifest entries; files already hashed with
+        /// another algorithm are still recognised as duplicates
+        #[arg(long, value_enum, default_value_t = HashAlgorithm::Sha256)]
+        hash: HashAlgorithm,
+        /// Hashing and metadata workers: a number, or `auto` to read
+        /// serially from rotational source disks
+        #[arg(long, default_value = "auto")]
+        jobs: Jobs,
+        /// How many workers may copy into the target at once, at most
+        /// --jobs: a number, or `auto` to write serially to rotational
+        /// target disks
+        #[arg(long, default_value = "auto")]
+        copy_jobs: Jobs,
+        /// Limit the combined write rate into the target, e.g. `50MiB/s`
+        #[arg(long, value_name = "RATE")]
+        max_bandwidth: Option<throttle::Bandwidth>,
+        /// Report format on stdout; `jsonl` writes one event per file and a
+        /// final summary, and implies --quiet
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        output: OutputFormat,
+        /// Start even if the target looks too small for this import
+        #[arg(long)]
+        force: bool,
+        /// Where to look for capture dates, in priority order; sources left
+        /// out are never used, e.g. `quicktime,exif` ignores filesystem dates
+        /// [default: exif,quicktime,filesystem]
+        #[arg(long, value_enum, value_delimiter = ',')]
+        date_sources: Option<Vec<BuiltinExtractor>>,
+        /// Folder for dated files, e.g. `{year}/{month}`; must match the
+        /// library's once it has been imported into
+        #[arg(long, value_parser = Template::layout)]
+        layout: Option<Template>,
+        /// File name for dated files, e.g. `{year}{month}{day}_{hour}{minute}{second}`;
+        /// must match the library's once it has been imported into
+        #[arg(long, value_parser = Template::naming)]
+        naming: Option<Template>,
+        /// Leave out files matching this gitignore-style pattern, relative
+        /// to SOURCE; repeatable, and applied after the built-in ignores,
+        /// SOURCE/.organiserignore and config files
+        #[arg(long, value_name = "PATTERN")]
+        exclude: Vec<String>,
+        /// Act as if the target had only this many bytes free (for tests)
+        #[arg(long, value_name = "BYTES", hide = true)]
+        simulate_free_space: Option<u64>,
+    },
+    /// Inspect a target library or change the layout and naming it is bound to
+    Library {
+        #[command(subcommand)]
+        action: LibraryAction,
+    },
+    /// Inspect the settings read from config files
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+    /// Manage the source hash cache kept in a target library
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
     },
 }
 
-fn format_hash(hash: &[u8; 32]) -> String {
-    let mut s = String::with_capacity(64);
-    for byte in hash {
-        s.push_str(&format!("{:02x}", byte));
-    }
-    s
-}
-
-fn date_source_string(source: &metadata::DateSource) -> &'static str {
-    match source {
-        metadata::DateSource::ExifDateTimeOriginal => "exif_datetime_original",
-        metadata::DateSource::ExifDateTimeDigitized => "exif_datetime_digitized",
-        metadata::DateSource::ExifDateTime => "exif_datetime",
-        metadata::DateSource::QuickTimeCreationDate => "quicktime_creation_date",
-        metadata::DateSource::QuickTimeMediaCreateDate => "quicktime_media_create_date",
-        metadata::DateSource::FilesystemCreated => "filesystem_created",
-        metadata::DateSource::FilesystemModified => "filesystem_modified",
-    }
-}
-
-#[derive(Debug, Clone)]
-struct ManifestEntry {
-    dir: PathBuf,
-    filename: String,
-    entry: manifest::FileEntry,
+#[derive(Subcommand)]
+enum CacheAction {
+    /// Delete the hash cache so every source file is re-hashed
+    Clear {
+        /// Target directory holding the cache
+        target: PathBuf,
+    },
 }
 
-#[derive(Debug)]
-enum FileProcessingResult {
-    Imported {
-        manifest_entry: Option<ManifestEntry>,
+#[derive(Subcommand)]
+enum LibraryAction {
+    /// Switch the library to a new layout or naming, moving existing files
+    /// and updating their manifests (default: dry-run)
+    Migrate {
+        /// Target library to migrate
+        target: PathBuf,
+        /// New folder layout for dated files
+        #[arg(long, value_parser = Template::layout)]
+        layout: Option<Template>,
+        /// New file naming for dated files
+        #[arg(long, value_parser = Template::naming)]
+        naming: Option<Template>,
+        /// Actually move the files (default: dry-run)
+        #[arg(long)]
+        execute: bool,
     },
-    Duplicate {
-        manifest_entry: Option<ManifestEntry>,
+    /// List library files that have duplicates recorded in duplicates/
+    Duplicates {
+        /// Target library to inspect
+        target: PathBuf,
     },
-    Undated {
-        manifest_entry: Option<ManifestEntry>,
+}
+
+#[derive(Subcommand)]
+enum ConfigAction {
+    /// Print the settings an import into TARGET would use, merged from the
+    /// user config and the target's .image-organiser.toml
+    Show {
+        /// Target directory whose project config to include
+        target: Option<PathBuf>,
     },
-    Corrupt,
 }
 
-fn now_iso8601() -> String {
-    jiff::Timestamp::now().strftime("%Y-%m-%dT%H:%M:%SZ").to_string()
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum OutputFormat {
+    /// Per-file lines on stderr and a summary line on stdout
+    Text,
+    /// One JSON object per line on stdout, see `events::Event`
+    Jsonl,
 }
 
-fn create_manifest_entry(
-    dest: &Path,
-    hex_hash: &str,
-    source_path: &Path,
-    original_name: &str,
-    date_source: Option<&str>,
-    source_group: Option<&str>,
-) -> ManifestEntry {
-    let file_size = source_path
-        .metadata()
-        .map(|m| m.len())
-        .unwrap_or(0);
-    let filename = dest
-        .file_name()
-        .map(|n| n.to_string_lossy().into_owned())
-        .unwrap_or_default();
-    let dir = dest.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+/// Process exit status. Usage errors exit with 2, from clap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ExitStatus {
+    /// Every file was imported, recognised as a duplicate or skipped
+    Clean = 0,
+    /// The command could not run, e.g. worker threads failed to start, or
+    /// an import could not save a manifest
+    Failed = 1,
+    /// The run completed, but some files are corrupt or undated
+    NeedsAttention = 3,
+    /// The target filled up during the run
+    DiskFull = 4,
+    /// Stopped by Ctrl-C; files already processed are recorded
+    Interrupted = 130,
+}
 
-    ManifestEntry {
-        dir,
-        filename,
-        entry: manifest::FileEntry {
-            sha256: hex_hash.to_string(),
-            original_path: source_path.to_string_lossy().into_owned(),
-            original_name: original_name.to_string(),
-            date_source: date_source.map(|s| s.to_string()),
-            source_group: source_group.map(|s| s.to_string()),
-            imported_at: now_iso8601(),
-            file_size_bytes: file_size,
-        },
+impl ExitStatus {
+    fn exit(self) -> ! {
+        std::process::exit(self as i32)
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-fn process_file_for_copy(
-    path: &Path,
-    extension: &str,
-    dedup_index: &std::collections::HashMap<String, PathBuf>,
-    target: &Path,
-    execute: bool,
-    move_files: bool,
-    file_op_lock: &std::sync::Arc<std::sync::Mutex<()>>,
+
+/// Per-file lines and warnings on stderr, drawn around the progress bar.
+struct Terminal {
+    progress: ProgressBar,
     quiet: bool,
-) -> FileProcessingResult {
-    let dry_run_prefix = if execute { "" } else { "[DRY RUN] " };
-    let op_word = if move_files { "MOVE" } else { "COPY" };
-    // Extract source_group from filename
-    let source_group = path
-        .file_name()
-        .and_then(|n| n.to_str())
-        .and_then(scan::extract_source_group);
+    /// `--output=jsonl`: file events go to stdout instead of stderr
+    jsonl: bool,
+    duplicates: DuplicatePolicy,
+    move_files: bool,
+}
 
-    // Step 1: Hash file
-    let hash = match metadata::hash_file(path) {
-        Ok(h) => h,
-        Err(err) => {
-            if err.kind() == std::io::ErrorKind::NotFound {
-                eprintln!(
-                    "WARNING: Source file disappeared: {}",
-                    path.display()
-                );
-            } else {
-                eprintln!("CORRUPT: {} ({})", path.display(), err);
-                if execute {
-                    let corrupt_dir = target.join("corrupt");
-                    let original_name = path
-                        .file_name()
-                        .map(|n| n.to_string_lossy().into_owned())
-                        .unwrap_or_else(|| "unknown".to_string());
-                    if let Err(e) = copy_to_dir(path, &corrupt_dir, &original_name) {
-                        eprintln!(
-                            "WARNING: Failed to quarantine {}: {}",
-                            path.display(),
-                            e
-                        );
-                    }
-                }
+impl Terminal {
+    fn line(&self, event: &FileEvent) -> Option<String> {
+        let prefix = if event.dry_run { "[DRY RUN] " } else { "" };
+        let source = &event.source;
+        let error = event.error.as_deref().unwrap_or_default();
+        let op = if self.move_files { "MOVE" } else { "COPY" };
+        match event.category {
+            // Files that need a look are listed even with --quiet
+            Category::Skipped => Some(format!("SKIPPED: {} ({})", source, error)),
+            Category::Corrupt => Some(format!("{}CORRUPT: {} ({})", prefix, source, error)),
+            _ if self.quiet => None,
+            Category::Imported | Category::Undated => {
+                let word = if event.category == Category::Undated && event.dry_run { "UNDATED" } else { op };
+                let destination = event.destination.as_deref().unwrap_or_default();
+                Some(format!("{}{} {} -> {}", prefix, word, source, destination))
             }
-            return FileProcessingResult::Corrupt;
-        }
-    };
-
-    let hex_hash = format_hash(&hash);
-
-    // Step 2: Check for duplicates
-    if let Some(existing) = dedup_index.get(&hex_hash) {
-        if execute {
-            let dup_dir = target.join("duplicates");
-            let original_name = path
-                .file_name()
-                .map(|n| n.to_string_lossy().into_owned())
-                .unwrap_or_else(|| "unknown".to_string());
-            match copy_to_dir(path, &dup_dir, &original_name) {
-                Ok(dest) => {
-                    if !quiet {
-                        eprintln!(
-                            "DUPLICATE {} -> {} (same as {})",
-                            path.display(),
-                            dest.display(),
-                            existing.display()
-                        );
-                    }
-                    let manifest_entry = create_manifest_entry(&dest, &hex_hash, path, &original_name, None, source_group.as_deref());
-                    if move_files {
-                        remove_source_safely(path, &dest);
-                    }
-                    return FileProcessingResult::Duplicate {
-                        manifest_entry: Some(manifest_entry),
-                    };
-                }
-                Err(e) => {
-                    if e.kind() != std::io::ErrorKind::NotFound {
-                        eprintln!(
-                            "WARNING: Failed to copy duplicate {}: {}",
-                            path.display(),
-                            e
-                        );
-                    }
-                }
+            Category::Duplicate => {
+                let canonical = event.duplicate_of.as_deref().unwrap_or_default();
+                let outcome = match self.duplicates {
+                    _ if event.dry_run => "",
+                    DuplicatePolicy::Copy => "",
+                    DuplicatePolicy::Skip => ", skipped",
+                    DuplicatePolicy::Record => ", recorded",
+                    DuplicatePolicy::DeleteSource if event.source_removed => ", source removed",
+                    DuplicatePolicy::DeleteSource => ", source preserved",
+                };
+                Some(match &event.destination {
+                    Some(destination) => format!("DUPLICATE {} -> {} (same as {})", source, destination, canonical),
+                    None => format!("{}DUPLICATE {} (same as {}){}", prefix, source, canonical, outcome),
+                })
             }
-        } else if !quiet {
-            eprintln!(
-                "{}DUPLICATE {} (same as {})",
-                dry_run_prefix,
-                path.display(),
-                existing.display()
-            );
         }
-        return FileProcessingResult::Duplicate {
-            manifest_entry: None,
-        };
     }
+}
 
-    // Step 3: Extract date
-    let date = metadata::extract_date(path);
-
-    match &date {
-        metadata::DateExtracted::Found { year, month, source, .. } => {
-            let dest_dir = target.join(format!("{:04}", year)).join(format!("{:02}", month));
+impl Observer for Terminal {
+    fn phase(&self, phase: Phase, total_bytes: u64) {
+        self.progress.reset();
+        self.progress.set_length(total_bytes);
+        self.progress.set_message(match phase {
+            Phase::Hashing => "hashing",
+            Phase::Importing => "importing",
+        });
+    }
 
-            // Lock to prevent race condition in filename generation + copy
-            let (_filename, dest) = {
-                let _lock = file_op_lock.lock().unwrap();
-                let filename = manifest::generate_filename(&date, extension, &hash, &dest_dir);
-                let dest = dest_dir.join(&filename);
-                (filename, dest)
-            };
+    fn advance(&self, bytes: u64) {
+        self.progress.inc(bytes);
+    }
 
-            if execute {
-                let _lock = file_op_lock.lock().unwrap();
-                match copy_file_to(path, &dest) {
-                    Ok(()) => {
-                        if !quiet {
-                            eprintln!(
-                                "{} {} -> {}",
-                                op_word,
-                                path.display(),
-                                dest.display()
-                            );
-                        }
-                        let original_name = path
-                            .file_name()
-                            .map(|n| n.to_string_lossy().into_owned())
-                            .unwrap_or_else(|| "unknown".to_string());
-                        let manifest_entry = create_manifest_entry(
-                            &dest,
-                            &hex_hash,
-                            path,
-                            &original_name,
-                            Some(date_source_string(source)),
-                            source_group.as_deref(),
-                        );
-                        if move_files {
-                            remove_source_safely(path, &dest);
-                        }
-                        FileProcessingResult::Imported {
-                            manifest_entry: Some(manifest_entry),
-                        }
-                    }
-                    Err(e) => {
-                        if is_disk_full(&e) {
-                            std::fs::remove_file(&dest).ok();
-                            eprintln!("ERROR: Target disk full");
-                        }
-                        if e.kind() != std::io::ErrorKind::NotFound {
-                            eprintln!(
-                                "CORRUPT: {} (copy failed: {})",
-                                path.display(),
-                                e
-                            );
-                        }
-                        FileProcessingResult::Corrupt
-                    }
-                }
-            } else {
-                if !quiet {
-                    eprintln!(
-                        "{}{} {} -> {}",
-                        dry_run_prefix,
-                        op_word,
-                        path.display(),
-                        dest.display()
-                    );
-                }
-                FileProcessingResult::Imported {
-                    manifest_entry: None,
-                }
-            }
+    fn file(&self, event: &FileEvent) {
+        if self.jsonl {
+            events::emit(&events::Event::File(event.clone()));
+        } else if let Some(line) = self.line(event) {
+            self.progress.suspend(|| eprintln!("{}", line));
         }
-        metadata::DateExtracted::NotFound => {
-            let dest_dir = target.join("undated");
-            let original_stem = path
-                .file_stem()
-                .map(|s| s.to_string_lossy().into_owned())
-                .unwrap_or_default();
-            let hash_suffix = format!("{:02x}{:02x}", hash[0], hash[1]);
-            let filename = format!("{}_{}.{}", original_stem, hash_suffix, extension);
-            let dest = dest_dir.join(&filename);
+    }
 
-            if execute {
-                let _lock = file_op_lock.lock().unwrap();
-                match copy_file_to(path, &dest) {
-                    Ok(()) => {
-                        if !quiet {
-                            eprintln!(
-                                "{} {} -> {}",
-                                op_word,
-                                path.display(),
-                                dest.display()
-                            );
-                        }
-                        let original_name = path
-                            .file_name()
-                            .map(|n| n.to_string_lossy().into_owned())
-                            .unwrap_or_else(|| "unknown".to_string());
-                        let manifest_entry =
-                            create_manifest_entry(&dest, &hex_hash, path, &original_name, None, source_group.as_deref());
-                        if move_files {
-                            remove_source_safely(path, &dest);
-                        }
-                        FileProcessingResult::Undated {
-                            manifest_entry: Some(manifest_entry),
-                        }
-                    }
-                    Err(e) => {
-                        if is_disk_full(&e) {
-                            std::fs::remove_file(&dest).ok();
-                            eprintln!("ERROR: Target disk full");
-                        }
-                        if e.kind() != std::io::ErrorKind::NotFound {
-                            eprintln!(
-                                "CORRUPT: {} (copy failed: {})",
-                                path.display(),
-                                e
-                            );
-                        }
-                        FileProcessingResult::Corrupt
-                    }
-                }
-            } else {
-                if !quiet {
-                    eprintln!(
-                        "{}UNDATED {} -> {}",
-                        dry_run_prefix,
-                        path.display(),
-                        dest.display()
-                    );
-                }
-                FileProcessingResult::Undated {
-                    manifest_entry: None,
-                }
-            }
-        }
+    fn warning(&self, message: &str) {
+        self.progress.suspend(|| eprintln!("WARNING: {}", message));
     }
 }
 
@@ -368,143 +262,346 @@ fn main() {
             execute,
             r#move: move_files,
             quiet,
+            duplicates,
+            no_cache,
+            hash: algorithm,
+            jobs,
+            copy_jobs,
+            max_bandwidth,
+            output,
+            force,
+            date_sources,
+            layout,
+            naming,
+            exclude,
+            simulate_free_space,
         } => {
-            let files = scan::discover_files(&source);
-            let dedup_index = manifest::build_dedup_index(&target);
-
-            let mut recognized: Vec<(PathBuf, String)> = Vec::new();
-            let mut skipped_count: usize = 0;
-            for file in &files {
-                match scan::classify_file(file) {
-                    scan::MediaFile::Recognized { path, extension } => {
-                        recognized.push((path, extension));
-                    }
-                    scan::MediaFile::Unrecognized { path, extension } => {
-                        if extension.is_empty() {
-                            eprintln!("SKIPPED: {} (no extension)", path.display());
-                        } else {
-                            eprintln!("SKIPPED: {} (.{} unrecognized)", path.display(), extension);
-                        }
-                        skipped_count += 1;
+            let jsonl = output == OutputFormat::Jsonl;
+            let quiet = quiet || jsonl;
+            let (config, _, bound) = load_config(Some(&target));
+            // The library's own settings cannot be overridden, only migrated
+            if let Some(bound) = &bound {
+                for (flag, value, setting) in [("--layout", &layout, &bound.layout), ("--naming", &naming, &bound.naming)] {
+                    if let Some(value) = value
+                        && value != setting {
+                        Cli::command()
+                            .error(
+                                clap::error::ErrorKind::ArgumentConflict,
+                                format!(
+                                    "{} \"{}\" conflicts with \"{}\", which the library in {} is bound to; run `image-organiser library migrate {} {}=...` to change it",
+                                    flag,
+                                    value,
+                                    setting,
+                                    target.display(),
+                                    target.display(),
+                                    flag
+                                ),
+                            )
+                            .exit();
                     }
                 }
             }
+            // Flags override the config files
+            let layout = layout.unwrap_or(config.layout);
+            let naming = naming.unwrap_or(config.naming);
+            let duplicates = duplicates.unwrap_or(config.duplicates);
+            let date_sources = date_sources.unwrap_or(config.date_sources);
+            if duplicates == DuplicatePolicy::DeleteSource && !move_files {
+                Cli::command()
+                    .error(
+                        clap::error::ErrorKind::MissingRequiredArgument,
+                        "--duplicates=delete-source requires --move",
+                    )
+                    .exit();
+            }
+            if let (Jobs::Fixed(copy), Jobs::Fixed(read)) = (copy_jobs, jobs)
+                && copy > read
+            {
+                Cli::command()
+                    .error(
+                        clap::error::ErrorKind::ArgumentConflict,
+                        format!("--copy-jobs={} exceeds --jobs={}: copies run on the read workers", copy, read),
+                    )
+                    .exit();
+            }
 
-            let progress = Arc::new(ProgressBar::new(recognized.len() as u64));
-            progress
-                .set_style(
-                    ProgressStyle::default_bar()
-                        .template("[{elapsed_precise}] [{bar:40}] {pos}/{len} ({eta})")
-                        .unwrap_or_else(|_| ProgressStyle::default_bar()), // safe: static template string
-                );
-
-            // Atomic counters for results
-            let imported_count = Arc::new(AtomicUsize::new(0));
-            let duplicate_count = Arc::new(AtomicUsize::new(0));
-            let corrupt_count = Arc::new(AtomicUsize::new(0));
-            let undated_count = Arc::new(AtomicUsize::new(0));
-
-            // Synchronize file operations to prevent race conditions in parallel mode
-            use std::sync::Mutex;
-            let file_op_lock = Arc::new(Mutex::new(()));
+            // Workers finish the file in hand and start no new ones
+            let interrupted = Arc::new(AtomicBool::new(false));
+            let flag = Arc::clone(&interrupted);
+            let interrupt_handler = ctrlc::set_handler(move || {
+                if flag.swap(true, Ordering::Relaxed) {
+                    ExitStatus::Interrupted.exit();
+                }
+                eprintln!("Interrupted: finishing files in progress (Ctrl-C again to stop immediately)");
+            });
+            if let Err(e) = interrupt_handler {
+                eprintln!("WARNING: Failed to install Ctrl-C handler: {}", e);
+            }
 
-            // Parallel processing
-            let results: Vec<_> = recognized
-                .par_iter()
-                .map(|(path, extension)| {
-                    let result = process_file_for_copy(
-                        path,
-                        extension,
-                        &dedup_index,
-                        &target,
-                        execute,
-                        move_files,
-                        &file_op_lock,
-                        quiet,
+            let started_at = import::now_iso8601();
+            let progress = ProgressBar::new(0);
+            progress.set_style(
+                ProgressStyle::default_bar()
+                    .template("{msg:9} [{elapsed_precise}] [{bar:40}] {binary_bytes}/{binary_total_bytes} {binary_bytes_per_sec} ({eta})")
+                    .unwrap_or_else(|_| ProgressStyle::default_bar()), // safe: static template string
+            );
+            let terminal = Terminal {
+                progress,
+                quiet,
+                jsonl,
+                duplicates,
+                move_files,
+            };
+            let importer = Importer::new(&source, &target)
+                .execute(execute)
+                .move_files(move_files)
+                .duplicates(duplicates)
+                .use_cache(!no_cache)
+                .hash_algorithm(algorithm)
+                .jobs(jobs)
+                .copy_jobs(copy_jobs)
+                .max_bandwidth(max_bandwidth)
+                .force(force)
+                .date_chain(DateChain::builtin(&date_sources))
+                .layout(layout)
+                .naming(naming)
+                .extensions(config.extensions)
+                .ignore(config.ignore.into_iter().chain(exclude).collect())
+                .cancel_flag(interrupted)
+                .simulate_free_space(simulate_free_space);
+            let outcome = importer.run(&terminal);
+            terminal.progress.finish_and_clear();
+            let outcome = match outcome {
+                Ok(outcome) => outcome,
+                Err(ImportError::InsufficientSpace { needed, available }) => {
+                    eprintln!(
+                        "ERROR: Not enough space in {}: this import needs {}, {} available (pass --force to start anyway)",
+                        target.display(),
+                        indicatif::BinaryBytes(needed),
+                        indicatif::BinaryBytes(available)
                     );
+                    ExitStatus::DiskFull.exit();
+                }
+                Err(e) => {
+                    eprintln!("ERROR: {}", e);
+                    ExitStatus::Failed.exit();
+                }
+            };
+            let summary = &outcome.summary;
 
-                    // Update counters
-                    match &result {
-                        FileProcessingResult::Imported { .. } => {
-                            imported_count.fetch_add(1, Ordering::Relaxed);
-                        }
-                        FileProcessingResult::Duplicate { .. } => {
-                            duplicate_count.fetch_add(1, Ordering::Relaxed);
-                        }
-                        FileProcessingResult::Undated { .. } => {
-                            undated_count.fetch_add(1, Ordering::Relaxed);
-                        }
-                        FileProcessingResult::Corrupt => {
-                            corrupt_count.fetch_add(1, Ordering::Relaxed);
-                        }
-                    }
-
-                    // Thread-safe progress update
-                    progress.inc(1);
-
-                    result
-                })
-                .collect();
+            if !quiet {
+                print_duplicate_clusters(&outcome.files, &source);
+            }
 
-            progress.finish_and_clear();
+            if let Some(Stopped::DiskFull {
+                remaining_files,
+                remaining_bytes,
+            }) = outcome.stopped
+            {
+                eprintln!(
+                    "ERROR: Target disk full. {} files were not imported; free up to {} in {} and re-run",
+                    remaining_files,
+                    indicatif::BinaryBytes(remaining_bytes),
+                    target.display()
+                );
+            }
+            for dir in &outcome.unsaved_manifests {
+                eprintln!(
+                    "ERROR: Could not save the manifest in {}; its new files were not imported and their sources were kept. Fix the folder and re-run",
+                    dir.display()
+                );
+            }
 
-            // Batch manifest updates
             if execute {
-                let mut manifest_batches: std::collections::HashMap<PathBuf, Vec<(String, manifest::FileEntry)>> =
-                    std::collections::HashMap::new();
+                let report = report::Report {
+                    version: env!("CARGO_PKG_VERSION"),
+                    command_line: std::env::args().collect(),
+                    started_at,
+                    finished_at: import::now_iso8601(),
+                    source: source.display().to_string(),
+                    target: target.display().to_string(),
+                    summary,
+                    files: &outcome.files,
+                };
+                match report::write_report(&target, &report) {
+                    Ok(dir) if !quiet => eprintln!("Report written to {}", dir.display()),
+                    Ok(_) => {}
+                    Err(e) => eprintln!("WARNING: Failed to write import report in {}: {}", target.display(), e),
+                }
+            }
 
-                for result in &results {
-                    match result {
-                        FileProcessingResult::Imported { manifest_entry }
-                        | FileProcessingResult::Duplicate { manifest_entry }
-                        | FileProcessingResult::Undated { manifest_entry } => {
-                            if let Some(entry) = manifest_entry {
-                                manifest_batches
-                                    .entry(entry.dir.clone())
-                                    .or_default()
-                                    .push((entry.filename.clone(), entry.entry.clone()));
-                            }
-                        }
-                        FileProcessingResult::Corrupt => {}
-                    }
+            let status = match outcome.stopped {
+                Some(Stopped::Interrupted) => ExitStatus::Interrupted,
+                Some(Stopped::DiskFull { .. }) => ExitStatus::DiskFull,
+                Some(Stopped::ManifestNotSaved) => ExitStatus::Failed,
+                None if summary.corrupt > 0 || summary.undated > 0 => ExitStatus::NeedsAttention,
+                None => ExitStatus::Clean,
+            };
+            if jsonl {
+                events::emit(&events::Event::Summary(outcome.summary));
+            } else {
+                print_summary(summary, execute);
+            }
+
+            status.exit();
+        }
+        Commands::Cache {
+            action: CacheAction::Clear { target },
+        } => match cache::clear_cache(&target) {
+            Ok(true) => println!("Cleared hash cache at {}", cache::cache_path(&target).display()),
+            Ok(false) => println!("No hash cache at {}", cache::cache_path(&target).display()),
+            Err(e) => {
+                eprintln!("ERROR: Failed to clear hash cache in {}: {}", target.display(), e);
+                ExitStatus::Failed.exit();
+            }
+        },
+        Commands::Library {
+            action:
+                LibraryAction::Migrate {
+                    target,
+                    layout,
+                    naming,
+                    execute,
+                },
+        } => {
+            let (config, _, _) = load_config(Some(&target));
+            let from = LibrarySettings::new(config.layout, config.naming);
+            let to = LibrarySettings::new(
+                layout.unwrap_or_else(|| from.layout.clone()),
+                naming.unwrap_or_else(|| from.naming.clone()),
+            );
+            let prefix = if execute { "" } else { "[DRY RUN] " };
+            let migration = match library::migrate(&target, &from, &to, execute, &warn) {
+                Ok(migration) => migration,
+                Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                    eprintln!("ERROR: {}", e);
+                    ExitStatus::Failed.exit();
+                }
+                Err(e) => {
+                    eprintln!(
+                        "ERROR: Migration of {} failed: {}. Folders already migrated keep the new layout; run the migration again to finish",
+                        target.display(),
+                        e
+                    );
+                    ExitStatus::Failed.exit();
+                }
+            };
+            for (from, to) in &migration.moved {
+                eprintln!("{}MOVE {} -> {}", prefix, from.display(), to.display());
+            }
+            for path in &migration.stranded {
+                eprintln!("WARNING: Cannot read the capture time back from {}, left in place", path.display());
+            }
+            println!(
+                "{}{} files moved, {} left in place",
+                prefix,
+                migration.moved.len(),
+                migration.stranded.len()
+            );
+            if !execute {
+                println!("\nPass --execute to perform operations.");
+            }
+        }
+        Commands::Library {
+            action: LibraryAction::Duplicates { target },
+        } => {
+            let clusters = index::LibraryIndex::open(&target)
+                .and_then(|mut ix| ix.refresh(&warn).map(|_| ix))
+                .and_then(|ix| ix.duplicate_clusters());
+            let clusters = match clusters {
+                Ok(clusters) => clusters,
+                Err(e) => {
+                    eprintln!("ERROR: Cannot read the library index in {}: {}", target.display(), e);
+                    ExitStatus::Failed.exit();
+                }
+            };
+            for cluster in &clusters {
+                println!("{} ({} duplicates)", cluster.canonical, cluster.duplicates.len());
+                for (entry, original_path) in &cluster.duplicates {
+                    println!("  {} from {}", entry, original_path);
                 }
+            }
+            println!(
+                "{} library files with {} duplicates",
+                clusters.len(),
+                clusters.iter().map(|c| c.duplicates.len()).sum::<usize>()
+            );
+        }
+        Commands::Config {
+            action: ConfigAction::Show { target },
+        } => {
+            let (config, files, _) = load_config(target.as_deref());
+            if files.is_empty() {
+                println!("# No config files found, showing defaults");
+            }
+            for file in &files {
+                println!("# From {}", file.display());
+            }
+            print!("{}", config.to_toml());
+        }
+    }
+}
 
-                // Write all manifests
-                for (dir, entries) in manifest_batches {
-                    let mut m = manifest::load_manifest(&dir);
-                    for (filename, file_entry) in entries {
-                        m.files.insert(filename, file_entry);
-                    }
-                    if let Err(e) = manifest::save_manifest(&dir, &m) {
-                        eprintln!("WARNING: Failed to save manifest in {}: {}", dir.display(), e);
-                    }
+/// Print a warning from a command that has no progress bar.
+fn warn(message: &str) {
+    eprintln!("WARNING: {}", message);
+}
+
+/// Merged config for an import into `target`, with the files it came from
+/// and the settings the library is bound to, which take precedence over
+/// config files. Exits on an invalid file.
+fn load_config(target: Option<&Path>) -> (Config, Vec<PathBuf>, Option<LibrarySettings>) {
+    let loaded = Config::load(target).and_then(|(config, files)| {
+        let bound = target.map(library::bound_settings).transpose()?.flatten();
+        Ok((config, files, bound))
+    });
+    match loaded {
+        Ok((mut config, mut files, bound)) => {
+            if let (Some(target), Some(bound)) = (target, &bound) {
+                config.layout = bound.layout.clone();
+                config.naming = bound.naming.clone();
+                let settings = library::settings_path(target);
+                if settings.exists() {
+                    files.push(settings);
                 }
             }
+            (config, files, bound)
+        }
+        Err(e) => {
+            eprintln!("ERROR: Invalid config {}", e);
+            ExitStatus::Failed.exit();
+        }
+    }
+}
 
-            let imported_count = imported_count.load(Ordering::SeqCst);
-            let duplicate_count = duplicate_count.load(Ordering::SeqCst);
-            let corrupt_count = corrupt_count.load(Ordering::SeqCst);
-            let undated_count = undated_count.load(Ordering::SeqCst);
-            print_summary(
-                imported_count,
-                duplicate_count,
-                corrupt_count,
-                undated_count,
-                skipped_count,
-                execute,
-            );
+/// List library files that more than one source file duplicated in this run.
+fn print_duplicate_clusters(files: &[FileEvent], source: &Path) {
+    let mut clusters: std::collections::BTreeMap<&str, usize> = std::collections::BTreeMap::new();
+    for file in files {
+        if let Some(canonical) = &file.duplicate_of {
+            *clusters.entry(canonical.as_str()).or_default() += 1;
         }
     }
+    clusters.retain(|_, count| *count > 1);
+    if clusters.is_empty() {
+        return;
+    }
+    eprintln!("Duplicate clusters in {}:", source.display());
+    for (canonical, count) in clusters {
+        eprintln!("  {} ({} copies)", canonical, count);
+    }
 }
 
-fn print_summary(
-    imported: usize,
-    duplicates: usize,
-    corrupt: usize,
-    undated: usize,
-    skipped: usize,
-    execute: bool,
-) {
+fn print_summary(summary: &SummaryEvent, execute: bool) {
+    let SummaryEvent {
+        imported,
+        duplicates,
+        corrupt,
+        undated,
+        skipped,
+        projected_bytes,
+        ..
+    } = summary;
     if execute {
         println!(
             "{} imported, {} duplicates, {} corrupt, {} undated, {} skipped",
@@ -515,87 +612,7 @@ fn print_summary(
             "[DRY RUN] {} imported, {} duplicates, {} corrupt, {} undated, {} skipped",
             imported, duplicates, corrupt, undated, skipped
         );
+        println!("[DRY RUN] {} would be written to the target", indicatif::BinaryBytes(*projected_bytes));
         println!("\nPass --execute to perform operations.");
     }
 }
-
-fn copy_file_to(source: &std::path::Path, dest: &std::path::Path) -> std::io::Result<()> {
-    if let Some(parent) = dest.parent() {
-        std::fs::create_dir_all(parent)?;
-    }
-    std::fs::copy(source, dest)?;
-    Ok(())
-}
-
-fn copy_to_dir(
-    source: &std::path::Path,
-    dir: &std::path::Path,
-    name: &str,
-) -> std::io::Result<PathBuf> {
-    std::fs::create_dir_all(dir)?;
-    let mut dest = dir.join(name);
-    if dest.exists() {
-        let stem = std::path::Path::new(name)
-            .file_stem()
-            .map(|s| s.to_string_lossy().into_owned())
-            .unwrap_or_default();
-        let ext = std::path::Path::new(name)
-            .extension()
-            .map(|s| s.to_string_lossy().into_owned())
-            .unwrap_or_default();
-        let mut counter = 1u32;
-        loop {
-            let candidate = if ext.is_empty() {
-                format!("{}_{}", stem, counter)
-            } else {
-                format!("{}_{}.{}", stem, counter, ext)
-            };
-            dest = dir.join(&candidate);
-            if !dest.exists() {
-                break;
-            }
-            counter += 1;
-            if counter > 1000 {
-                break;
-            }
-        }
-    }
-    std::fs::copy(source, &dest)?;
-    Ok(dest)
-}
-
-fn remove_source_safely(
-    source: &std::path::Path,
-    dest: &std::path::Path,
-) {
-    match (dest.exists(), dest.metadata()) {
-        (true, Ok(dest_meta)) => match source.metadata() {
-            Ok(src_meta) if dest_meta.len() == src_meta.len() => {
-                if let Err(e) = std::fs::remove_file(source) {
-                    eprintln!(
-                        "WARNING: Failed to remove source {}: {}",
-                        source.display(),
-                        e
-                    );
-                }
-            }
-            _ => {
-                eprintln!(
-                    "WARNING: Size mismatch after copy, source preserved: {}",
-                    source.display()
-                );
-            }
-        },
-        _ => {
-            eprintln!(
-                "WARNING: Dest verification failed, source preserved: {}",
-                source.display()
-            );
-        }
-    }
-}
-
-
-fn is_disk_full(err: &std::io::Error) -> bool {
-    err.raw_os_error() == Some(28)
-}
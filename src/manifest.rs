@@ -1,121 +1,334 @@
-use crate::metadata::DateExtracted;
+use crate::metadata::{self, Digests, HashAlgorithm};
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use walkdir::WalkDir;
 
-#[derive(Serialize, Deserialize)]
+/// Current manifest schema. Version 1 stored a `sha256` field; version 2
+/// stores `hash` together with its `hash_algorithm`. Older manifests are
+/// upgraded in memory on load and written back in the current schema.
+pub const MANIFEST_VERSION: u8 = 2;
+
+#[derive(Clone, Serialize, Deserialize)]
 pub struct Manifest {
     pub version: u8,
-    pub files: HashMap<String, FileEntry>,
+    /// Ordered by file name so a manifest's JSON depends only on its content
+    pub files: BTreeMap<String, FileEntry>,
 }
 
+/// Per-file manifest record. `hash` is empty (and omitted from the JSON)
+/// for quarantined files that could not be read far enough to hash.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct FileEntry {
-    pub sha256: String,
+    #[serde(default, alias = "sha256", skip_serializing_if = "String::is_empty")]
+    pub hash: String,
+    #[serde(default)]
+    pub hash_algorithm: HashAlgorithm,
     pub original_path: String,
     pub original_name: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub date_source: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub source_group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub corrupt_reason: Option<String>,
     pub imported_at: String,
     pub file_size_bytes: u64,
 }
 
-pub fn load_manifest(dir: &Path) -> Manifest {
+/// Read the manifest in `dir`; a missing one reads as empty. A corrupt one
+/// also reads as empty, and is reported through `warn`.
+pub fn load_manifest(dir: &Path, warn: &dyn Fn(&str)) -> Manifest {
     let path = dir.join(".manifest.json");
     let content = match std::fs::read_to_string(&path) {
         Ok(c) => c,
         Err(_) => {
             return Manifest {
-                version: 1,
-                files: HashMap::new(),
+                version: MANIFEST_VERSION,
+                files: BTreeMap::new(),
             };
         }
     };
-    match serde_json::from_str(&content) {
-        Ok(m) => m,
+    match serde_json::from_str::<Manifest>(&content) {
+        Ok(mut m) => {
+            m.version = MANIFEST_VERSION;
+            m
+        }
         Err(_) => {
-            eprintln!(
-                "WARNING: corrupt manifest at {}, starting fresh",
-                path.display()
-            );
+            warn(&format!("Corrupt manifest at {}, starting fresh", path.display()));
             Manifest {
-                version: 1,
-                files: HashMap::new(),
+                version: MANIFEST_VERSION,
+                files: BTreeMap::new(),
             }
         }
     }
 }
 
+/// Write the manifest to a temporary file and rename it into place, so an
+/// interrupted save never leaves a truncated manifest behind. Both are
+/// synced to disk before this returns.
 pub fn save_manifest(dir: &Path, manifest: &Manifest) -> std::io::Result<()> {
+    use std::io::Write;
+    std::fs::create_dir_all(dir)?;
     let path = dir.join(".manifest.json");
+    let tmp = dir.join(".manifest.json.tmp");
     let json = serde_json::to_string_pretty(manifest)
         .map_err(std::io::Error::other)?;
-    std::fs::write(&path, json)
+    let written = std::fs::File::create(&tmp).and_then(|mut file| {
+        file.write_all(json.as_bytes())?;
+        file.sync_all()
+    });
+    if let Err(e) = written {
+        std::fs::remove_file(&tmp).ok();
+        return Err(e);
+    }
+    std::fs::rename(&tmp, &path)?;
+    // Make the rename itself durable before callers act on it
+    #[cfg(unix)]
+    std::fs::File::open(dir)?.sync_all()?;
+    Ok(())
+}
+
+/// Content hashes already in the library, keyed by algorithm and mapped to
+/// the file holding them, plus the set of library file sizes. A file whose
+/// size is not in the set cannot be a duplicate, so it can skip hashing
+/// before the copy. Files quarantined in `corrupt/` are kept apart: they
+/// are never the canonical copy of anything.
+#[derive(Default)]
+pub struct DedupIndex {
+    paths: HashMap<(HashAlgorithm, String), PathBuf>,
+    quarantined: HashMap<(HashAlgorithm, String), PathBuf>,
+    sizes: HashSet<u64>,
+}
+
+/// Whether a path relative to the target lies in `duplicates/` or
+/// `corrupt/`.
+pub fn is_quarantined(relative: &str) -> bool {
+    let top = relative.split(['/', '\\']).next().unwrap_or_default();
+    top == "duplicates" || top == "corrupt"
+}
+
+impl DedupIndex {
+    pub fn get(&self, algorithm: HashAlgorithm, hash: &str) -> Option<&PathBuf> {
+        self.paths.get(&(algorithm, hash.to_string()))
+    }
+
+    /// First library file matching any of `digests`, with the algorithm
+    /// that matched.
+    pub fn find(&self, digests: &Digests) -> Option<(HashAlgorithm, &PathBuf)> {
+        digests.iter().find_map(|(algorithm, digest)| {
+            self.get(algorithm, &metadata::format_hash(&digest))
+                .map(|path| (algorithm, path))
+        })
+    }
+
+    /// File in `corrupt/` matching any of `digests`.
+    pub fn find_quarantined(&self, digests: &Digests) -> Option<&PathBuf> {
+        digests
+            .iter()
+            .find_map(|(algorithm, digest)| self.quarantined.get(&(algorithm, metadata::format_hash(&digest))))
+    }
+
+    /// Algorithms used by at least one indexed file.
+    pub fn algorithms(&self) -> HashSet<HashAlgorithm> {
+        self.paths
+            .keys()
+            .chain(self.quarantined.keys())
+            .map(|(algorithm, _)| *algorithm)
+            .collect()
+    }
+
+    pub fn contains_size(&self, size: u64) -> bool {
+        self.sizes.contains(&size)
+    }
+
+    pub fn insert(&mut self, algorithm: HashAlgorithm, hash: String, path: PathBuf, size: u64) {
+        self.paths.insert((algorithm, hash), path);
+        self.sizes.insert(size);
+    }
+
+    pub fn insert_quarantined(&mut self, algorithm: HashAlgorithm, hash: String, path: PathBuf, size: u64) {
+        self.quarantined.insert((algorithm, hash), path);
+        self.sizes.insert(size);
+    }
+
+    /// Index one manifest entry of `dir`, relative to `target`. Library
+    /// files are canonical. A `duplicates/` entry stands for the library
+    /// file it duplicates, which fills gaps such as a hash recorded under
+    /// another algorithm, but never replaces a library entry.
+    pub fn add(
+        &mut self,
+        target: &Path,
+        dir: &str,
+        filename: &str,
+        (algorithm, hash): (HashAlgorithm, String),
+        size: u64,
+        duplicate_of: Option<&str>,
+    ) {
+        if hash.is_empty() {
+            return;
+        }
+        if dir == "corrupt" {
+            self.insert_quarantined(algorithm, hash, target.join(dir).join(filename), size);
+        } else if dir == "duplicates" {
+            if let Some(canonical) = duplicate_of.filter(|c| !is_quarantined(c)) {
+                self.paths.entry((algorithm, hash)).or_insert_with(|| target.join(canonical));
+                self.sizes.insert(size);
+            }
+        } else {
+            self.insert(algorithm, hash, target.join(dir).join(filename), size);
+        }
+    }
 }
 
-pub fn build_dedup_index(target: &Path) -> HashMap<String, PathBuf> {
-    let mut index = HashMap::new();
+pub fn build_dedup_index(target: &Path, warn: &dyn Fn(&str)) -> DedupIndex {
+    let mut index = DedupIndex::default();
     if !target.exists() {
         return index;
     }
-    for entry in WalkDir::new(target)
+    let mut manifests: Vec<(String, Manifest)> = WalkDir::new(target)
         .into_iter()
         .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
-        if entry.file_name() == ".manifest.json" {
-            let dir = entry.path().parent().unwrap_or(target);
-            let manifest = load_manifest(dir);
-            for (filename, file_entry) in &manifest.files {
-                index.insert(file_entry.sha256.clone(), dir.join(filename));
-            }
+        .filter(|e| e.file_type().is_file() && e.file_name() == ".manifest.json")
+        .filter_map(|entry| {
+            let dir = entry.path().parent()?;
+            let relative = dir.strip_prefix(target).ok()?.to_string_lossy().into_owned();
+            Some((relative, load_manifest(dir, warn)))
+        })
+        .collect();
+    // Library files first, so duplicate references only fill gaps
+    manifests.sort_by_key(|(dir, _)| is_quarantined(dir));
+    for (dir, manifest) in &manifests {
+        for (filename, file_entry) in &manifest.files {
+            index.add(
+                target,
+                dir,
+                filename,
+                (file_entry.hash_algorithm, file_entry.hash.clone()),
+                file_entry.file_size_bytes,
+                file_entry.duplicate_of.as_deref(),
+            );
         }
     }
     index
 }
 
-pub fn generate_filename(
-    date: &DateExtracted,
-    extension: &str,
-    hash: &[u8; 32],
-    target_dir: &Path,
-) -> String {
-    if let DateExtracted::Found {
-        year,
-        month,
-        day,
-        hour,
-        minute,
-        second,
-        ..
-    } = date
-    {
-        let base = format!(
-            "{:04}{:02}{:02}_{:02}{:02}{:02}",
-            year, month, day, hour, minute, second
-        );
-        let candidate = format!("{}.{}", base, extension);
-        if !target_dir.join(&candidate).exists() {
-            return candidate;
+/// Names to try, in order, for a dated file whose name from the naming
+/// template is `base`: the bare name first, then increasingly distinct hash
+/// suffixes.
+pub fn filename_candidates(base: &str, extension: &str, hash: &[u8; 32]) -> Vec<String> {
+    let mut candidates = vec![format!("{}.{}", base, extension)];
+    candidates.extend(suffixed_candidates(base, extension, hash));
+    candidates
+}
+
+/// `{base}_{suffix}.{ext}` names whose suffix comes from successive byte
+/// pairs of the hash, ending with a longer 4-byte suffix.
+pub fn suffixed_candidates(base: &str, extension: &str, hash: &[u8; 32]) -> Vec<String> {
+    let mut candidates: Vec<String> = (0..10)
+        .map(|i| format!("{}_{:02x}{:02x}.{}", base, hash[i], hash[i + 1], extension))
+        .collect();
+    let long_suffix: String = hash[..4].iter().map(|b| format!("{:02x}", b)).collect();
+    candidates.push(format!("{}_{}.{}", base, long_suffix, extension));
+    candidates
+}
+
+/// `name` itself, then `{stem}_1.{ext}` onwards, for the quarantine folders
+/// that keep original file names.
+pub fn numbered_candidates(name: &str) -> Vec<String> {
+    let stem = Path::new(name)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let ext = Path::new(name)
+        .extension()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let numbered = (1..=1000).map(|counter| {
+        if ext.is_empty() {
+            format!("{}_{}", stem, counter)
+        } else {
+            format!("{}_{}.{}", stem, counter, ext)
+        }
+    });
+    std::iter::once(name.to_string()).chain(numbered).collect()
+}
+
+/// Destination names handed out during a run. A candidate is only allocated
+/// if no other file in the run holds it and it is free on disk, so a dry run
+/// proposes exactly the names an execute run claims. With `on_disk` set,
+/// each name is also claimed on disk with an exclusive create, so it never
+/// replaces a file already there. Runs writing into the same target are
+/// kept apart by the target lock, not by this.
+pub struct NameAllocator {
+    reserved: Mutex<HashSet<PathBuf>>,
+    on_disk: bool,
+}
+
+impl NameAllocator {
+    pub fn new(on_disk: bool) -> Self {
+        NameAllocator {
+            reserved: Mutex::new(HashSet::new()),
+            on_disk,
         }
-        let suffix = format!("{:02x}{:02x}", hash[0], hash[1]);
-        let candidate = format!("{}_{}.{}", base, suffix, extension);
-        if !target_dir.join(&candidate).exists() {
-            return candidate;
+    }
+
+    /// Treat `names` in `dir` as taken, e.g. manifest entries that have no
+    /// file on disk.
+    pub fn reserve(&self, dir: &Path, names: impl IntoIterator<Item = String>) {
+        let mut reserved = self.reserved.lock().unwrap();
+        reserved.extend(names.into_iter().map(|name| dir.join(name)));
+    }
+
+    /// Claim the first free name among `candidates` in `dir`. On disk the
+    /// name is held by an empty placeholder the caller replaces.
+    pub fn allocate(&self, dir: &Path, candidates: Vec<String>) -> std::io::Result<PathBuf> {
+        self.claim(dir, candidates, self.on_disk)
+    }
+
+    /// Claim a name for a manifest entry that has no file, such as a
+    /// `--duplicates=record` entry. The name is only held in memory, but
+    /// still avoids files on disk and every other name in the run.
+    pub fn allocate_record(&self, dir: &Path, candidates: Vec<String>) -> std::io::Result<PathBuf> {
+        self.claim(dir, candidates, false)
+    }
+
+    fn claim(&self, dir: &Path, candidates: Vec<String>, on_disk: bool) -> std::io::Result<PathBuf> {
+        if on_disk {
+            std::fs::create_dir_all(dir)?;
         }
-        for i in 1..10 {
-            let suffix = format!("{:02x}{:02x}", hash[i], hash[i + 1]);
-            let candidate = format!("{}_{}.{}", base, suffix, extension);
-            if !target_dir.join(&candidate).exists() {
-                return candidate;
+        for candidate in candidates {
+            let dest = dir.join(candidate);
+            if !self.reserved.lock().unwrap().insert(dest.clone()) {
+                continue;
+            }
+            let free = if on_disk {
+                create_placeholder(&dest)?
+            } else {
+                !dest.exists()
+            };
+            if free {
+                return Ok(dest);
             }
         }
-        let long_suffix: String = hash[..4].iter().map(|b| format!("{:02x}", b)).collect();
-        format!("{}_{}.{}", base, long_suffix, extension)
-    } else {
-        format!("undated.{}", extension)
+        Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            format!("no free file name in {}", dir.display()),
+        ))
+    }
+}
+
+/// Create `path` empty with `create_new`. Returns `false` if it already
+/// exists.
+fn create_placeholder(path: &Path) -> std::io::Result<bool> {
+    match std::fs::OpenOptions::new().write(true).create_new(true).open(path) {
+        Ok(_) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e),
     }
 }
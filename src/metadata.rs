@@ -1,6 +1,8 @@
+use crate::throttle::{self, Throttle};
+use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::fs::File;
-use std::io::{BufReader, Read};
+use std::io::{Cursor, Read, Seek, Write};
 use std::path::Path;
 
 #[derive(Debug, Clone)]
@@ -26,39 +28,282 @@ pub enum DateSource {
     QuickTimeMediaCreateDate,
     FilesystemCreated,
     FilesystemModified,
+    /// Found by a `DateExtractor` outside this module, under its name
+    Other(&'static str),
 }
 
-pub fn hash_file(path: &Path) -> std::io::Result<[u8; 32]> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
-    let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 8192];
+impl DateSource {
+    /// Name recorded as the manifest's `date_source`.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DateSource::ExifDateTimeOriginal => "exif_datetime_original",
+            DateSource::ExifDateTimeDigitized => "exif_datetime_digitized",
+            DateSource::ExifDateTime => "exif_datetime",
+            DateSource::QuickTimeCreationDate => "quicktime_creation_date",
+            DateSource::QuickTimeMediaCreateDate => "quicktime_media_create_date",
+            DateSource::FilesystemCreated => "filesystem_created",
+            DateSource::FilesystemModified => "filesystem_modified",
+            DateSource::Other(name) => name,
+        }
+    }
+}
+
+/// A file whose capture date is wanted: its path, and its contents when
+/// already read into memory.
+pub struct DateInput<'a> {
+    pub path: &'a Path,
+    pub contents: Option<&'a [u8]>,
+}
+
+trait ReadSeek: Read + Seek {}
+
+impl<T: Read + Seek> ReadSeek for T {}
+
+impl DateInput<'_> {
+    /// Reader over the contents, from memory when buffered.
+    fn open(&self) -> Option<Box<dyn ReadSeek + '_>> {
+        match self.contents {
+            Some(bytes) => Some(Box::new(Cursor::new(bytes))),
+            None => File::open(self.path).ok().map(|f| Box::new(f) as Box<dyn ReadSeek>),
+        }
+    }
+}
+
+/// One way of finding a file's capture date. `DateChain` asks each
+/// extractor in turn and keeps the first date found.
+pub trait DateExtractor: Send + Sync {
+    fn extract(&self, input: &DateInput) -> Option<DateExtracted>;
+}
+
+/// Built-in extractors, named as in `--date-sources`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BuiltinExtractor {
+    /// EXIF DateTimeOriginal, CreateDate, then ModifyDate
+    Exif,
+    /// QuickTime creation date, then media creation time
+    Quicktime,
+    /// Filesystem creation time, then modification time
+    Filesystem,
+}
+
+impl BuiltinExtractor {
+    /// The default priority: embedded metadata first, filesystem last.
+    pub const ALL: [BuiltinExtractor; 3] = [
+        BuiltinExtractor::Exif,
+        BuiltinExtractor::Quicktime,
+        BuiltinExtractor::Filesystem,
+    ];
+}
+
+impl DateExtractor for BuiltinExtractor {
+    fn extract(&self, input: &DateInput) -> Option<DateExtracted> {
+        match self {
+            BuiltinExtractor::Exif => input.open().and_then(try_exif_dates),
+            BuiltinExtractor::Quicktime => input.open().and_then(try_quicktime_dates),
+            BuiltinExtractor::Filesystem => try_filesystem_dates(input.path),
+        }
+    }
+}
+
+/// Extractors in priority order. Leaving one out disables it.
+#[derive(Clone)]
+pub struct DateChain {
+    extractors: Vec<std::sync::Arc<dyn DateExtractor>>,
+}
+
+impl DateChain {
+    pub fn new(extractors: Vec<std::sync::Arc<dyn DateExtractor>>) -> Self {
+        DateChain { extractors }
+    }
+
+    pub fn builtin(order: &[BuiltinExtractor]) -> Self {
+        DateChain::new(
+            order
+                .iter()
+                .map(|e| std::sync::Arc::new(*e) as std::sync::Arc<dyn DateExtractor>)
+                .collect(),
+        )
+    }
+
+    pub fn extract(&self, input: &DateInput) -> DateExtracted {
+        self.extractors
+            .iter()
+            .find_map(|e| e.extract(input))
+            .unwrap_or(DateExtracted::NotFound)
+    }
+}
+
+impl Default for DateChain {
+    fn default() -> Self {
+        DateChain::builtin(&BuiltinExtractor::ALL)
+    }
+}
+
+impl std::fmt::Debug for DateChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "DateChain({} extractors)", self.extractors.len())
+    }
+}
+
+/// Content hash algorithm. Both produce 32-byte digests; SHA-256 is the
+/// default, BLAKE3 is several times faster on fast storage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl HashAlgorithm {
+    pub const ALL: [HashAlgorithm; 2] = [HashAlgorithm::Sha256, HashAlgorithm::Blake3];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha256 => "sha256",
+            HashAlgorithm::Blake3 => "blake3",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<HashAlgorithm> {
+        HashAlgorithm::ALL.into_iter().find(|a| a.as_str() == name)
+    }
+}
+
+/// Digests of one file under one or more algorithms, computed in a single
+/// read so a file can be checked against a library hashed with another
+/// algorithm.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct Digests {
+    pub sha256: Option<[u8; 32]>,
+    pub blake3: Option<[u8; 32]>,
+}
+
+impl Digests {
+    pub fn get(&self, algorithm: HashAlgorithm) -> Option<[u8; 32]> {
+        match algorithm {
+            HashAlgorithm::Sha256 => self.sha256,
+            HashAlgorithm::Blake3 => self.blake3,
+        }
+    }
+
+    pub fn set(&mut self, algorithm: HashAlgorithm, digest: [u8; 32]) {
+        match algorithm {
+            HashAlgorithm::Sha256 => self.sha256 = Some(digest),
+            HashAlgorithm::Blake3 => self.blake3 = Some(digest),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (HashAlgorithm, [u8; 32])> + '_ {
+        HashAlgorithm::ALL
+            .into_iter()
+            .filter_map(|algorithm| Some((algorithm, self.get(algorithm)?)))
+    }
+}
+
+struct MultiHasher {
+    sha256: Option<Sha256>,
+    blake3: Option<Box<blake3::Hasher>>,
+}
+
+impl MultiHasher {
+    fn new(algorithms: &[HashAlgorithm]) -> Self {
+        MultiHasher {
+            sha256: algorithms.contains(&HashAlgorithm::Sha256).then(Sha256::new),
+            blake3: algorithms
+                .contains(&HashAlgorithm::Blake3)
+                .then(|| Box::new(blake3::Hasher::new())),
+        }
+    }
+
+    fn update(&mut self, bytes: &[u8]) {
+        if let Some(hasher) = &mut self.sha256 {
+            hasher.update(bytes);
+        }
+        if let Some(hasher) = &mut self.blake3 {
+            hasher.update(bytes);
+        }
+    }
+
+    fn finalize(self) -> Digests {
+        Digests {
+            sha256: self.sha256.map(|h| h.finalize().into()),
+            blake3: self.blake3.map(|h| *h.finalize().as_bytes()),
+        }
+    }
+}
+
+pub fn format_hash(hash: &[u8; 32]) -> String {
+    let mut s = String::with_capacity(64);
+    for byte in hash {
+        s.push_str(&format!("{:02x}", byte));
+    }
+    s
+}
+
+/// Inverse of `format_hash`.
+pub fn parse_hash(s: &str) -> Option<[u8; 32]> {
+    if s.len() != 64 {
+        return None;
+    }
+    let mut out = [0u8; 32];
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(s.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+/// Read size for hashing and streamed copies. Large sequential reads keep
+/// spinning disks and USB card readers near their streaming throughput.
+const IO_CHUNK: usize = 1024 * 1024;
+
+pub fn hash_file(path: &Path, algorithms: &[HashAlgorithm]) -> std::io::Result<Digests> {
+    let mut file = File::open(path)?;
+    let mut hasher = MultiHasher::new(algorithms);
+    let mut buffer = vec![0u8; IO_CHUNK];
     loop {
-        let bytes_read = reader.read(&mut buffer)?;
+        let bytes_read = file.read(&mut buffer)?;
         if bytes_read == 0 {
             break;
         }
         hasher.update(&buffer[..bytes_read]);
     }
-    Ok(hasher.finalize().into())
+    Ok(hasher.finalize())
 }
 
-pub fn extract_date(path: &Path) -> DateExtracted {
-    if let Some(result) = try_exif_dates(path) {
-        return result;
-    }
-    if let Some(result) = try_quicktime_dates(path) {
-        return result;
-    }
-    if let Some(result) = try_filesystem_dates(path) {
-        return result;
+pub fn hash_bytes(contents: &[u8], algorithms: &[HashAlgorithm]) -> Digests {
+    let mut hasher = MultiHasher::new(algorithms);
+    hasher.update(contents);
+    hasher.finalize()
+}
+
+/// Copy `source` to `dest` while hashing it, so the bytes are read once.
+/// Writes are paced by `throttle` when one is set.
+pub fn copy_and_hash(
+    source: &Path,
+    dest: &Path,
+    algorithms: &[HashAlgorithm],
+    throttle: Option<&Throttle>,
+) -> std::io::Result<Digests> {
+    let mut reader = File::open(source)?;
+    let mut writer = File::create(dest)?;
+    let mut hasher = MultiHasher::new(algorithms);
+    let mut buffer = vec![0u8; IO_CHUNK];
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+        throttle::write_all(&mut writer, &buffer[..bytes_read], throttle)?;
     }
-    DateExtracted::NotFound
+    writer.flush()?;
+    Ok(hasher.finalize())
 }
 
-fn try_exif_dates(path: &Path) -> Option<DateExtracted> {
-    let file = File::open(path).ok()?;
-    let iter = nom_exif::parse_exif(file, None).ok()??;
+fn try_exif_dates<R: Read>(reader: R) -> Option<DateExtracted> {
+    let iter = nom_exif::parse_exif(reader, None).ok()??;
     let exif: nom_exif::Exif = iter.into();
 
     let tag_chain = [
@@ -76,9 +321,8 @@ fn try_exif_dates(path: &Path) -> Option<DateExtracted> {
     None
 }
 
-fn try_quicktime_dates(path: &Path) -> Option<DateExtracted> {
-    let file = File::open(path).ok()?;
-    let entries = nom_exif::parse_metadata(file).ok()?;
+fn try_quicktime_dates<R: Read + Seek>(reader: R) -> Option<DateExtracted> {
+    let entries = nom_exif::parse_metadata(reader).ok()?;
 
     let qt_keys: &[(&str, DateSource)] = &[
         ("com.apple.quicktime.creationdate", DateSource::QuickTimeCreationDate),
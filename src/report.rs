@@ -0,0 +1,83 @@
+use crate::events::{FileEvent, SummaryEvent};
+use serde::Serialize;
+use std::fmt::Write as _;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Directory under the target holding one subdirectory per import run.
+const REPORTS_DIR: &str = ".imports";
+
+/// Persistent record of one `--execute` run, written as `report.json` and a
+/// human-readable `report.txt` under `<TARGET>/.imports/<run-id>/`.
+#[derive(Serialize)]
+pub struct Report<'a> {
+    pub version: &'static str,
+    pub command_line: Vec<String>,
+    pub started_at: String,
+    pub finished_at: String,
+    pub source: String,
+    pub target: String,
+    pub summary: &'a SummaryEvent,
+    /// Every source file, skipped ones first, then in processing order
+    pub files: &'a [FileEvent],
+}
+
+/// Write the report into a new run directory named after its start time,
+/// e.g. `20240115T143022Z`, with a numeric suffix if that name is taken.
+/// Returns the run directory.
+pub fn write_report(target: &Path, report: &Report) -> io::Result<PathBuf> {
+    let reports = target.join(REPORTS_DIR);
+    std::fs::create_dir_all(&reports)?;
+    let base: String = report.started_at.chars().filter(|c| *c != '-' && *c != ':').collect();
+    let mut run_dir = reports.join(&base);
+    let mut n = 1;
+    loop {
+        match std::fs::create_dir(&run_dir) {
+            Ok(()) => break,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                n += 1;
+                run_dir = reports.join(format!("{}-{}", base, n));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    let json = serde_json::to_string_pretty(report).map_err(io::Error::other)?;
+    std::fs::write(run_dir.join("report.json"), json)?;
+    std::fs::write(run_dir.join("report.txt"), render_text(&run_dir, report))?;
+    Ok(run_dir)
+}
+
+fn render_text(run_dir: &Path, report: &Report) -> String {
+    let run_id = run_dir.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+    let summary = report.summary;
+    let mut out = String::new();
+    // Writing to a String cannot fail
+    let _ = writeln!(out, "image-organiser {} import {}", report.version, run_id);
+    let _ = writeln!(out, "Command:  {}", report.command_line.join(" "));
+    let _ = writeln!(out, "Source:   {}", report.source);
+    let _ = writeln!(out, "Target:   {}", report.target);
+    let _ = writeln!(out, "Started:  {}", report.started_at);
+    let _ = writeln!(out, "Finished: {}", report.finished_at);
+    let _ = writeln!(
+        out,
+        "\n{} imported, {} duplicates, {} corrupt, {} undated, {} skipped\n",
+        summary.imported, summary.duplicates, summary.corrupt, summary.undated, summary.skipped
+    );
+    for file in report.files {
+        let _ = write!(out, "{:9} {}", file.category.as_str(), file.source);
+        if let Some(destination) = &file.destination {
+            let _ = write!(out, " -> {}", destination);
+        }
+        if let Some(date_source) = &file.date_source {
+            let _ = write!(out, " [{}]", date_source);
+        }
+        if let Some(duplicate_of) = &file.duplicate_of {
+            let _ = write!(out, " (same as {})", duplicate_of);
+        }
+        if let Some(error) = &file.error {
+            let _ = write!(out, " ({})", error);
+        }
+        out.push('\n');
+    }
+    out
+}
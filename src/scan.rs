@@ -6,24 +6,37 @@ pub enum MediaFile {
     Unrecognized { path: PathBuf, extension: String },
 }
 
-pub fn discover_files(source: &Path) -> Vec<PathBuf> {
+/// Every file under `source` that `ignore` does not rule out. Ignored
+/// directories are not descended into.
+pub fn discover_files(source: &Path, ignore: &IgnoreRules) -> Vec<PathBuf> {
     WalkDir::new(source)
         .into_iter()
+        .filter_entry(|entry| entry.depth() == 0 || !ignore.matches(entry.path(), entry.file_type().is_dir()))
         .filter_map(|entry| entry.ok())
         .filter(|entry| entry.file_type().is_file())
         .map(|entry| entry.into_path())
         .collect()
 }
 
+/// Extensions imported unless a config file lists its own.
+pub const DEFAULT_EXTENSIONS: &[&str] = &[
+    "heic", "heif", "jpeg", "jpg", "png", "tiff", "tif", "webp", "bmp", "gif",
+    "avif", "cr2", "cr3", "nef", "arw", "raf", "rw2", "dng", "orf", "pef",
+    "srw", "3fr", "mov", "mp4", "m4v", "avi", "mkv", "3gp", "aae",
+];
+
 pub fn classify_file(path: &Path) -> MediaFile {
+    classify_file_with(path, DEFAULT_EXTENSIONS)
+}
+
+/// Like `classify_file`, recognising only `extensions` (lowercase, no dot).
+pub fn classify_file_with(path: &Path, extensions: &[impl AsRef<str>]) -> MediaFile {
     let extension = path
         .extension()
         .map(|ext| ext.to_string_lossy().to_lowercase())
         .unwrap_or_default();
 
-    let recognized = matches!(extension.as_str(), "heic" | "heif" | "jpeg" | "jpg" | "png" | "tiff" | "tif" | "webp" | "bmp" | "gif"
-        | "avif" | "cr2" | "cr3" | "nef" | "arw" | "raf" | "rw2" | "dng" | "orf" | "pef"
-        | "srw" | "3fr" | "mov" | "mp4" | "m4v" | "avi" | "mkv" | "3gp" | "aae");
+    let recognized = !extension.is_empty() && extensions.iter().any(|e| e.as_ref() == extension);
 
     if recognized {
         MediaFile::Recognized {
@@ -38,6 +51,73 @@ pub fn classify_file(path: &Path) -> MediaFile {
     }
 }
 
+/// Operating system and tool clutter that is never media: macOS trash,
+/// search index and AppleDouble resource forks, Windows thumbnail caches
+/// and freedesktop thumbnail folders.
+pub const BUILTIN_IGNORES: &[&str] = &[
+    ".Trashes/",
+    ".Spotlight-V100/",
+    ".fseventsd/",
+    "__MACOSX/",
+    "._*",
+    ".DS_Store",
+    "Thumbs.db",
+    ".thumbnails/",
+];
+
+/// Ignore file read from the root of an import source, in gitignore syntax.
+pub const IGNORE_FILE: &str = ".organiserignore";
+
+/// Gitignore-style patterns, relative to the import source, for files that
+/// are left out of an import entirely.
+pub struct IgnoreRules {
+    matcher: ignore::gitignore::Gitignore,
+}
+
+impl IgnoreRules {
+    /// Only `patterns`, without the built-in ignores.
+    pub fn new(source: &Path, patterns: &[String]) -> Result<Self, ignore::Error> {
+        let mut builder = ignore::gitignore::GitignoreBuilder::new(source);
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+        Ok(IgnoreRules {
+            matcher: builder.build()?,
+        })
+    }
+
+    /// The built-in ignores, then the source's `.organiserignore`, then
+    /// `patterns`. Later rules win, so a `!` pattern can bring back a file
+    /// an earlier rule ignored.
+    pub fn for_source(source: &Path, patterns: &[String]) -> Result<Self, ignore::Error> {
+        let mut builder = ignore::gitignore::GitignoreBuilder::new(source);
+        for pattern in BUILTIN_IGNORES {
+            builder.add_line(None, pattern)?;
+        }
+        builder.add_line(None, &format!("/{}", IGNORE_FILE))?;
+        let ignore_file = source.join(IGNORE_FILE);
+        if ignore_file.is_file()
+            && let Some(e) = builder.add(&ignore_file) {
+            return Err(e);
+        }
+        for pattern in patterns {
+            builder.add_line(None, pattern)?;
+        }
+        Ok(IgnoreRules {
+            matcher: builder.build()?,
+        })
+    }
+
+    /// Whether the file at `path`, or a directory above it, is ignored.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        self.matches(path, false)
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        self.matcher.matched_path_or_any_parents(path, is_dir).is_ignore()
+    }
+}
+
 pub fn extract_source_group(filename: &str) -> Option<String> {
     if filename.is_empty() {
         return None;
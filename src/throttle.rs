@@ -0,0 +1,105 @@
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Chunk size for throttled writes. Small enough that a slow limit still
+/// paces smoothly instead of in long bursts.
+const THROTTLE_CHUNK: usize = 256 * 1024;
+
+/// A transfer rate in bytes per second, parsed from forms like `50MiB/s`,
+/// `10MB/s`, `500K` or a plain byte count. `K`, `M` and `G` alone are binary
+/// multiples; `KB`, `MB` and `GB` are decimal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bandwidth(u64);
+
+impl Bandwidth {
+    pub fn bytes_per_second(self) -> u64 {
+        self.0
+    }
+}
+
+impl FromStr for Bandwidth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("expected a rate like `50MiB/s` or `10MB/s`, got `{}`", s);
+        let rate = s.trim();
+        let rate = rate.strip_suffix("/s").unwrap_or(rate);
+        let split = rate
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rate.len());
+        let (number, unit) = rate.split_at(split);
+        let number: f64 = number.parse().map_err(|_| invalid())?;
+        let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 1,
+            "k" | "kib" => 1 << 10,
+            "m" | "mib" => 1 << 20,
+            "g" | "gib" => 1 << 30,
+            "kb" => 1_000,
+            "mb" => 1_000_000,
+            "gb" => 1_000_000_000,
+            _ => return Err(invalid()),
+        };
+        let bytes = (number * multiplier as f64) as u64;
+        if bytes == 0 {
+            return Err(invalid());
+        }
+        Ok(Bandwidth(bytes))
+    }
+}
+
+/// Rate limiter shared by every copy writer. Each write reserves a slot on
+/// a common schedule and sleeps until the slot ends, so the combined write
+/// rate across threads stays at the limit.
+pub struct Throttle {
+    bytes_per_second: u64,
+    next_free: Mutex<Instant>,
+}
+
+impl Throttle {
+    pub fn new(bandwidth: Bandwidth) -> Self {
+        Throttle {
+            bytes_per_second: bandwidth.bytes_per_second(),
+            next_free: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Block long enough to keep the rate under the limit after `bytes`
+    /// more bytes are written.
+    pub fn wait(&self, bytes: usize) {
+        let cost = Duration::from_secs_f64(bytes as f64 / self.bytes_per_second as f64);
+        let until = {
+            let mut next_free = self.next_free.lock().unwrap();
+            *next_free = (*next_free).max(Instant::now()) + cost;
+            *next_free
+        };
+        std::thread::sleep(until.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// Write `bytes` to `writer`, paced by `throttle` when one is set.
+pub fn write_all<W: Write>(writer: &mut W, bytes: &[u8], throttle: Option<&Throttle>) -> std::io::Result<()> {
+    match throttle {
+        None => writer.write_all(bytes),
+        Some(throttle) => {
+            for chunk in bytes.chunks(THROTTLE_CHUNK) {
+                throttle.wait(chunk.len());
+                writer.write_all(chunk)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// `std::fs::write`, paced by `throttle` when one is set.
+pub fn write_file(path: &Path, bytes: &[u8], throttle: Option<&Throttle>) -> std::io::Result<()> {
+    if throttle.is_none() {
+        return std::fs::write(path, bytes);
+    }
+    let mut file = File::create(path)?;
+    write_all(&mut file, bytes, throttle)?;
+    file.flush()
+}
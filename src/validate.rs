@@ -0,0 +1,204 @@
+use std::fs::File;
+use std::io::{BufReader, Cursor, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+
+const JPEG_SOI: [u8; 2] = [0xFF, 0xD8];
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+/// Container format detected from a file's leading bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Jpeg,
+    Png,
+    IsoBmff,
+    Unknown,
+}
+
+/// Check that a file's container structure is intact.
+///
+/// The container is sniffed from content rather than extension, so a PNG
+/// saved as `.jpg` is validated as a PNG. Files whose format is not
+/// recognised pass unchecked. Structural problems are reported as
+/// `ErrorKind::InvalidData` with a human-readable reason; any other error
+/// kind is a genuine I/O failure.
+pub fn validate_structure(path: &Path) -> std::io::Result<()> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    check_structure(file, len)
+}
+
+/// Like `validate_structure`, for contents already in memory.
+pub fn validate_bytes(contents: &[u8]) -> std::io::Result<()> {
+    check_structure(Cursor::new(contents), contents.len() as u64)
+}
+
+fn check_structure<R: Read + Seek>(mut reader: R, len: u64) -> std::io::Result<()> {
+    if len == 0 {
+        return Err(invalid("zero-length file"));
+    }
+
+    let mut head = [0u8; 12];
+    let head_len = read_up_to(&mut reader, &mut head)?;
+    reader.seek(SeekFrom::Start(0))?;
+
+    match sniff(&head[..head_len]) {
+        Container::Jpeg => check_jpeg(reader),
+        Container::Png => check_png(reader, len),
+        Container::IsoBmff => check_iso_bmff(reader, len),
+        Container::Unknown => Ok(()),
+    }
+}
+
+fn sniff(head: &[u8]) -> Container {
+    if head.starts_with(&JPEG_SOI) {
+        Container::Jpeg
+    } else if head.starts_with(&PNG_SIGNATURE) {
+        Container::Png
+    } else if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        Container::IsoBmff
+    } else {
+        Container::Unknown
+    }
+}
+
+fn invalid(reason: &str) -> std::io::Error {
+    std::io::Error::new(ErrorKind::InvalidData, reason.to_string())
+}
+
+fn read_up_to<R: Read>(reader: &mut R, buf: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        let n = reader.read(&mut buf[filled..])?;
+        if n == 0 {
+            break;
+        }
+        filled += n;
+    }
+    Ok(filled)
+}
+
+/// Walk marker segments up to the first SOS, then scan entropy-coded data
+/// for the EOI marker. Data appended after EOI (e.g. motion photo trailers)
+/// is tolerated; an EOI inside an APP segment (embedded thumbnail) is not
+/// mistaken for the image's own.
+fn check_jpeg<R: Read>(reader: R) -> std::io::Result<()> {
+    let mut reader = BufReader::new(reader);
+    let mut marker = [0u8; 2];
+    reader.read_exact(&mut marker)?;
+
+    loop {
+        let mut byte = [0u8; 1];
+        if reader.read(&mut byte)? == 0 {
+            return Err(invalid("truncated JPEG: ends before image data"));
+        }
+        if byte[0] != 0xFF {
+            return Err(invalid("malformed JPEG: expected marker"));
+        }
+        // Markers may be preceded by any number of 0xFF fill bytes
+        let mut code = 0xFF;
+        while code == 0xFF {
+            if reader.read(&mut byte)? == 0 {
+                return Err(invalid("truncated JPEG: ends before image data"));
+            }
+            code = byte[0];
+        }
+        match code {
+            0xD9 => return Err(invalid("malformed JPEG: EOI before image data")),
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+        let mut length = [0u8; 2];
+        if reader.read_exact(&mut length).is_err() {
+            return Err(invalid("truncated JPEG: ends inside segment header"));
+        }
+        let length = u16::from_be_bytes(length);
+        if length < 2 {
+            return Err(invalid("malformed JPEG: invalid segment length"));
+        }
+        let skipped = std::io::copy(
+            &mut (&mut reader).take(u64::from(length - 2)),
+            &mut std::io::sink(),
+        )?;
+        if skipped < u64::from(length - 2) {
+            return Err(invalid("truncated JPEG: ends inside segment"));
+        }
+        if code == 0xDA {
+            break;
+        }
+    }
+
+    let mut buffer = [0u8; 8192];
+    let mut previous_ff = false;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            return Err(invalid("truncated JPEG: missing EOI marker"));
+        }
+        for &b in &buffer[..n] {
+            if previous_ff && b == 0xD9 {
+                return Ok(());
+            }
+            previous_ff = b == 0xFF;
+        }
+    }
+}
+
+/// Walk the chunk list, requiring every chunk to fit in the file and the
+/// stream to end with IEND.
+fn check_png<R: Read + Seek>(mut file: R, len: u64) -> std::io::Result<()> {
+    let mut offset = PNG_SIGNATURE.len() as u64;
+    file.seek(SeekFrom::Start(offset))?;
+    loop {
+        if offset + 8 > len {
+            return Err(invalid("truncated PNG: missing IEND chunk"));
+        }
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let data_len = u64::from(u32::from_be_bytes([header[0], header[1], header[2], header[3]]));
+        let chunk_end = offset + 12 + data_len;
+        if chunk_end > len {
+            return Err(invalid("truncated PNG: chunk extends past end of file"));
+        }
+        if &header[4..8] == b"IEND" {
+            return Ok(());
+        }
+        offset = chunk_end;
+        file.seek(SeekFrom::Start(offset))?;
+    }
+}
+
+/// Walk the top-level box tree, requiring every box size to be sane and to
+/// fit in the file.
+fn check_iso_bmff<R: Read + Seek>(mut file: R, len: u64) -> std::io::Result<()> {
+    let mut offset = 0u64;
+    while offset < len {
+        if offset + 8 > len {
+            return Err(invalid("truncated ISO-BMFF: partial box header"));
+        }
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header)?;
+        let size = u64::from(u32::from_be_bytes([header[0], header[1], header[2], header[3]]));
+        let (box_size, header_size) = match size {
+            // Box extends to end of file
+            0 => return Ok(()),
+            1 => {
+                if offset + 16 > len {
+                    return Err(invalid("truncated ISO-BMFF: partial box header"));
+                }
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large)?;
+                (u64::from_be_bytes(large), 16)
+            }
+            s => (s, 8),
+        };
+        if box_size < header_size {
+            return Err(invalid("malformed ISO-BMFF: invalid box size"));
+        }
+        match offset.checked_add(box_size) {
+            Some(end) if end <= len => offset = end,
+            _ => return Err(invalid("truncated ISO-BMFF: box extends past end of file")),
+        }
+    }
+    Ok(())
+}
@@ -1,11 +1,12 @@
 use assert_cmd::cargo::cargo_bin_cmd;
+use image_organiser::metadata::HashAlgorithm;
 use predicates::prelude::*;
 use std::fs;
 use std::path::Path;
 use tempfile::TempDir;
 
 fn cmd() -> assert_cmd::Command {
-    cargo_bin_cmd!("image-organiser").into()
+    cargo_bin_cmd!("image-organiser")
 }
 
 fn create_file(dir: &Path, name: &str, content: &[u8]) {
@@ -16,6 +17,11 @@ fn create_file(dir: &Path, name: &str, content: &[u8]) {
     fs::write(&path, content).expect("write test file");
 }
 
+fn sha256_hex(path: &Path) -> String {
+    let digests = image_organiser::metadata::hash_file(path, &[HashAlgorithm::Sha256]).unwrap();
+    image_organiser::metadata::format_hash(&digests.sha256.unwrap())
+}
+
 fn read_manifest(dir: &Path) -> serde_json::Value {
     let path = dir.join(".manifest.json");
     let content = fs::read_to_string(&path).expect("read manifest");
@@ -88,20 +94,20 @@ fn execute_creates_organized_structure_with_manifests() {
                     let month_dir = month_entry.unwrap().path();
                     if month_dir.is_dir() {
                         let manifest = read_manifest(&month_dir);
-                        assert_eq!(manifest["version"], 1);
+                        assert_eq!(manifest["version"], 2);
 
-                        if let Some(files) = manifest["files"].as_object() {
-                            if !files.is_empty() {
+                        if let Some(files) = manifest["files"].as_object()
+                            && !files.is_empty() {
                                 manifest_found = true;
                                 for (_filename, entry) in files {
-                                    assert!(entry["sha256"].is_string(), "sha256 must be present");
+                                    assert!(entry["hash"].is_string(), "hash must be present");
+                                    assert_eq!(entry["hash_algorithm"], "sha256");
                                     assert!(entry["original_path"].is_string(), "original_path must be present");
                                     assert!(entry["original_name"].is_string(), "original_name must be present");
                                     assert!(entry["imported_at"].is_string(), "imported_at must be present");
                                     assert!(entry["file_size_bytes"].is_number(), "file_size_bytes must be present");
                                 }
                             }
-                        }
                     }
                 }
             }
@@ -166,6 +172,296 @@ fn idempotent_rerun_shows_all_duplicates() {
         .stdout(predicate::str::contains("3 duplicates"));
 }
 
+#[test]
+fn identical_files_in_one_import_are_deduplicated() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "backup1/DCIM/IMG_0001.jpg", b"same photo");
+    create_file(source.path(), "backup2/DCIM/IMG_0001.jpg", b"same photo");
+    create_file(source.path(), "backup2/DCIM/IMG_0002.jpg", b"other photo");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 imported, 1 duplicates"));
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 imported, 1 duplicates"));
+
+    // The copy later in source order is always the duplicate
+    let dup_manifest = read_manifest(&target.path().join("duplicates"));
+    let dup_files = dup_manifest["files"].as_object().expect("files is object");
+    assert_eq!(dup_files.len(), 1);
+    let entry = dup_files.values().next().unwrap();
+    assert!(entry["original_path"].as_str().unwrap().contains("backup2"));
+    let canonical = entry["duplicate_of"].as_str().expect("duplicate_of recorded");
+    assert!(target.path().join(canonical).exists());
+}
+
+#[test]
+fn duplicate_entries_reference_canonical_file() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "photo.jpg", b"same content");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    let second_source = TempDir::new().unwrap();
+    create_file(second_source.path(), "a/photo.jpg", b"same content");
+    create_file(second_source.path(), "b/photo.jpg", b"same content");
+
+    cmd()
+        .args(["import", second_source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("Duplicate clusters"))
+        .stderr(predicate::str::contains("(2 copies)"));
+
+    // A third run must still resolve to the library file, not a duplicates/ copy
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    let dup_manifest = read_manifest(&target.path().join("duplicates"));
+    let dup_files = dup_manifest["files"].as_object().expect("files is object");
+    assert_eq!(dup_files.len(), 3);
+    for entry in dup_files.values() {
+        let canonical = entry["duplicate_of"].as_str().expect("duplicate_of recorded");
+        assert!(!canonical.starts_with("duplicates"), "canonical must be a library file, got {}", canonical);
+        assert!(target.path().join(canonical).exists());
+    }
+
+    // Clusters across all runs come from the library index
+    let canonical = dup_files.values().next().unwrap()["duplicate_of"].as_str().unwrap().to_string();
+    cmd()
+        .args(["library", "duplicates", target.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains(format!("{} (3 duplicates)", canonical)))
+        .stdout(predicate::str::contains("a/photo.jpg"))
+        .stdout(predicate::str::contains("1 library files with 3 duplicates"));
+}
+
+#[test]
+fn record_policy_writes_manifest_entry_without_copy() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "photo.jpg", b"same content");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    let second_source = TempDir::new().unwrap();
+    create_file(second_source.path(), "again.jpg", b"same content");
+
+    cmd()
+        .args([
+            "import",
+            second_source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--duplicates=record",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 duplicates"));
+
+    let dup_dir = target.path().join("duplicates");
+    let dup_manifest = read_manifest(&dup_dir);
+    let dup_files = dup_manifest["files"].as_object().expect("files is object");
+    assert_eq!(dup_files.len(), 1);
+    let (name, entry) = dup_files.iter().next().unwrap();
+    assert!(!dup_dir.join(name).exists(), "record policy must not copy the file");
+    assert_eq!(entry["original_name"], "again.jpg");
+    let canonical = entry["duplicate_of"].as_str().expect("duplicate_of recorded");
+    assert!(target.path().join(canonical).exists(), "duplicate_of must point at the library file");
+    assert!(second_source.path().join("again.jpg").exists(), "record policy must not touch the source");
+}
+
+#[test]
+fn recorded_duplicates_with_the_same_name_keep_separate_entries() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "photo.jpg", b"same content");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    // The same file from two cards
+    let cards = TempDir::new().unwrap();
+    create_file(cards.path(), "card1/DCIM/IMG_0001.JPG", b"same content");
+    create_file(cards.path(), "card2/DCIM/IMG_0001.JPG", b"same content");
+    for policy in ["--duplicates=record", "--duplicates=copy"] {
+        cmd()
+            .args(["import", cards.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute", policy])
+            .assert()
+            .success()
+            .stdout(predicate::str::contains("2 duplicates"));
+    }
+
+    let dup_dir = target.path().join("duplicates");
+    let dup_files = read_manifest(&dup_dir)["files"].as_object().expect("files is object").clone();
+    assert_eq!(dup_files.len(), 4, "every duplicate keeps its own entry: {:?}", dup_files.keys());
+    let originals: std::collections::BTreeSet<&str> =
+        dup_files.values().map(|e| e["original_path"].as_str().unwrap()).collect();
+    assert_eq!(originals.len(), 2);
+    let copied: Vec<&String> = dup_files.keys().filter(|name| dup_dir.join(name).exists()).collect();
+    assert_eq!(copied.len(), 2, "copies must not take the keys of recorded entries");
+}
+
+#[test]
+fn skip_policy_leaves_no_trace() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "photo.jpg", b"same content");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--duplicates=skip",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 imported, 1 duplicates"));
+
+    assert!(!target.path().join("duplicates").exists(), "skip policy must not create duplicates/");
+}
+
+#[test]
+fn delete_source_policy_requires_move() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--duplicates=delete-source",
+        ])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("requires --move"));
+}
+
+#[test]
+fn delete_source_policy_removes_verified_duplicates() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "photo.jpg", b"same content");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    let second_source = TempDir::new().unwrap();
+    create_file(second_source.path(), "again.jpg", b"same content");
+
+    cmd()
+        .args([
+            "import",
+            second_source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--move",
+            "--duplicates=delete-source",
+        ])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("source removed"));
+
+    assert!(!second_source.path().join("again.jpg").exists(), "verified duplicate source must be removed");
+    let dup_manifest = read_manifest(&target.path().join("duplicates"));
+    assert_eq!(dup_manifest["files"].as_object().unwrap().len(), 1, "removal must be recorded");
+}
+
+#[test]
+fn hash_streamed_during_copy_matches_source() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    // Sizes unique to this batch and the (empty) library, so hashing is deferred to the copy
+    create_file(source.path(), "a.jpg", b"content a");
+    create_file(source.path(), "b.png", b"longer content b");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 imported"));
+
+    let mut hashes = std::collections::HashMap::new();
+    for entry in walkdir::WalkDir::new(target.path()).into_iter().flatten() {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        assert!(!name.ends_with(".partial"), "partial copy left behind: {}", name);
+        if name == ".manifest.json" {
+            let manifest = read_manifest(entry.path().parent().unwrap());
+            for file in manifest["files"].as_object().unwrap().values() {
+                hashes.insert(
+                    file["original_name"].as_str().unwrap().to_string(),
+                    file["hash"].as_str().unwrap().to_string(),
+                );
+            }
+        }
+    }
+    for name in ["a.jpg", "b.png"] {
+        let expected = sha256_hex(&source.path().join(name));
+        assert_eq!(hashes[name], expected, "manifest hash for {} must match content", name);
+    }
+}
+
+#[test]
+fn files_too_large_to_buffer_are_streamed() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    let size = image_organiser::buffer::MAX_BUFFERED_BYTES as usize + 1;
+    let content: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+    create_file(source.path(), "long.mov", &content);
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 imported"));
+
+    let copied: Vec<_> = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'))
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .collect();
+    assert_eq!(copied.len(), 1);
+    assert_eq!(fs::read(copied[0].path()).unwrap(), content, "streamed copy must be byte-identical");
+}
+
 // --- S5: Timestamp Collision Resolution ---
 
 #[test]
@@ -187,17 +483,16 @@ fn collision_resolution_preserves_all_files() {
     let mut all_hashes = std::collections::HashSet::new();
 
     fn collect_from_dir(dir: &std::path::Path, total_files: &mut usize, all_hashes: &mut std::collections::HashSet<String>) {
-        if dir.exists() && dir.is_dir() {
-            if dir.join(".manifest.json").exists() {
+        if dir.exists() && dir.is_dir()
+            && dir.join(".manifest.json").exists() {
                 let manifest = read_manifest(dir);
                 if let Some(files) = manifest["files"].as_object() {
                     *total_files += files.len();
                     for (_filename, entry) in files {
-                        all_hashes.insert(entry["sha256"].as_str().unwrap().to_string());
+                        all_hashes.insert(entry["hash"].as_str().unwrap().to_string());
                     }
                 }
             }
-        }
     }
 
     // Check dated folders
@@ -226,6 +521,131 @@ fn collision_resolution_preserves_all_files() {
     assert_eq!(all_hashes.len(), 3, "all 3 files must have distinct hashes");
 }
 
+#[test]
+fn concurrent_same_timestamp_files_get_distinct_names() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    let shared_mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    for i in 0..24 {
+        let name = format!("burst_{:02}.png", i);
+        create_file(source.path(), &name, format!("burst shot {}", i).as_bytes());
+        fs::File::options()
+            .write(true)
+            .open(source.path().join(&name))
+            .unwrap()
+            .set_modified(shared_mtime)
+            .unwrap();
+    }
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute", "--jobs=8"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("24 imported"));
+
+    let manifest_dir = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .find(|e| e.file_name() == ".manifest.json")
+        .map(|e| e.path().parent().unwrap().to_path_buf())
+        .expect("manifest written");
+    let manifest = read_manifest(&manifest_dir);
+    let files = manifest["files"].as_object().unwrap();
+    assert_eq!(files.len(), 24, "every burst shot needs its own name");
+    for (name, entry) in files {
+        assert_eq!(
+            sha256_hex(&manifest_dir.join(name)),
+            entry["hash"].as_str().unwrap(),
+            "{} must hold the content its manifest entry records",
+            name
+        );
+    }
+}
+
+#[test]
+fn dry_run_proposes_the_names_execute_uses() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    let shared_mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    for i in 0..4 {
+        let name = format!("burst_{}.png", i);
+        create_file(source.path(), &name, format!("burst shot {}", i).as_bytes());
+        fs::File::options()
+            .write(true)
+            .open(source.path().join(&name))
+            .unwrap()
+            .set_modified(shared_mtime)
+            .unwrap();
+    }
+    let placements = |args: &[&str]| -> Vec<String> {
+        let output = cmd()
+            .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--jobs=1"])
+            .args(args)
+            .output()
+            .unwrap();
+        let mut lines: Vec<String> = String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter(|line| line.contains(" -> "))
+            .map(|line| line.trim_start_matches("[DRY RUN] ").to_string())
+            .collect();
+        lines.sort();
+        lines
+    };
+
+    let planned = placements(&[]);
+    let destinations: std::collections::HashSet<_> = planned.iter().map(|line| line.split(" -> ").nth(1)).collect();
+    assert_eq!(destinations.len(), 4, "dry run must not propose one name twice: {:?}", planned);
+    assert_eq!(planned, placements(&["--execute"]));
+}
+
+#[test]
+fn repeated_imports_produce_identical_trees() {
+    let source = TempDir::new().unwrap();
+    let shared_mtime = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_700_000_000);
+    for i in 0..16 {
+        let name = format!("dir_{}/burst.png", i % 4);
+        let name = if i < 4 { name } else { format!("dir_{}/burst_{}.png", i % 4, i) };
+        create_file(source.path(), &name, format!("burst shot {}", i % 12).as_bytes());
+        fs::File::options()
+            .write(true)
+            .open(source.path().join(&name))
+            .unwrap()
+            .set_modified(shared_mtime)
+            .unwrap();
+    }
+
+    let import = || {
+        let target = TempDir::new().unwrap();
+        cmd()
+            .env("SOURCE_DATE_EPOCH", "1700000000")
+            .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute", "--jobs=8"])
+            .assert()
+            .success();
+        let mut tree = std::collections::BTreeMap::new();
+        // Run reports under .imports/ record paths and times, so differ per run
+        let runs = walkdir::WalkDir::new(target.path())
+            .into_iter()
+            .filter_entry(|e| e.file_name() != ".imports");
+        for entry in runs.flatten() {
+            let name = entry.file_name().to_string_lossy();
+            let derived = name.starts_with('.') && name != ".manifest.json";
+            if entry.file_type().is_file() && !derived {
+                let relative = entry.path().strip_prefix(target.path()).unwrap().to_path_buf();
+                tree.insert(relative, fs::read(entry.path()).unwrap());
+            }
+        }
+        tree
+    };
+
+    let first = import();
+    assert!(first.keys().any(|p| p.starts_with("duplicates")), "fixture must exercise duplicates");
+    for _ in 0..3 {
+        assert!(first == import(), "re-running the import must reproduce the tree byte for byte");
+    }
+}
+
 // --- S6: Move vs Copy Semantics ---
 
 #[test]
@@ -291,23 +711,134 @@ fn move_removes_source_files() {
     assert_eq!(total_files, 1, "file must be in target");
 }
 
-// --- S7: Undated File Handling ---
-
 #[test]
-fn files_without_metadata_use_filesystem_dates() {
+fn move_places_files_in_batches() {
     let source = TempDir::new().unwrap();
     let target = TempDir::new().unwrap();
+    for i in 0..300 {
+        create_file(source.path(), &format!("img_{:03}.png", i), format!("content {}", i).as_bytes());
+    }
 
-    create_file(source.path(), "screenshot.png", b"plain png bytes");
+    cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--move",
+            "--quiet",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("300 imported"));
+
+    assert_eq!(fs::read_dir(source.path()).unwrap().count(), 0, "every moved source must be removed");
+    let names: std::collections::HashSet<String> = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_type().is_file())
+        .map(|e| e.file_name().to_string_lossy().into_owned())
+        .collect();
+    assert!(names.iter().all(|name| !name.ends_with(".partial")));
+    assert_eq!(names.iter().filter(|name| name.ends_with(".png")).count(), 300);
+}
 
+#[test]
+fn partial_copies_of_earlier_imports_are_removed() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"photo a");
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    let month = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .find(|e| e.path().extension().is_some_and(|ext| ext == "png"))
+        .map(|e| e.path().parent().unwrap().to_path_buf())
+        .unwrap();
+
+    // One left by a crashed import, and one whose pid is running here but
+    // could be another host's: neither run holds the target lock any more
+    let mut crashed = std::process::Command::new("true").spawn().unwrap();
+    crashed.wait().unwrap();
+    let stale = format!(".{}-0.partial", crashed.id());
+    let other_host = format!(".{}-0.partial", std::process::id());
+    create_file(&month, &stale, b"half a photo");
+    create_file(&month, &other_host, b"half a photo");
+    create_file(&month, ".notes.partial", b"not a partial copy");
+
+    create_file(source.path(), "b.png", b"photo b");
     cmd()
         .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
         .assert()
         .success()
-        .stdout(predicate::str::contains("1 imported").or(predicate::str::contains("0 undated")));
+        .stdout(predicate::str::contains("1 imported"));
 
-    // File should be in a dated folder (using filesystem date), not undated/
-    let mut found_file = false;
+    assert!(!month.join(&stale).exists(), "partial copy of a dead import must be removed");
+    assert!(!month.join(&other_host).exists(), "partial copies of runs without the lock must be removed");
+    assert!(month.join(".notes.partial").exists(), "only partial copies are removed");
+}
+
+#[test]
+fn move_keeps_sources_when_manifest_cannot_be_saved() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"photo a");
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    let month = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .find(|e| e.path().extension().is_some_and(|ext| ext == "png"))
+        .map(|e| e.path().parent().unwrap().to_path_buf())
+        .unwrap();
+    // The temporary manifest cannot be created over a directory
+    fs::create_dir(month.join(".manifest.json.tmp")).unwrap();
+
+    create_file(source.path(), "b.png", b"photo b");
+    cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--move",
+        ])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Could not save the manifest"));
+
+    assert!(source.path().join("b.png").exists(), "source must be kept when its manifest is not saved");
+    let pngs = fs::read_dir(&month)
+        .unwrap()
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "png"))
+        .count();
+    assert_eq!(pngs, 1, "the unrecorded copy must be taken out again");
+    assert_eq!(read_manifest(&month)["files"].as_object().unwrap().len(), 1);
+}
+
+// --- S7: Undated File Handling ---
+
+#[test]
+fn files_without_metadata_use_filesystem_dates() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "screenshot.png", b"plain png bytes");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 imported").or(predicate::str::contains("0 undated")));
+
+    // File should be in a dated folder (using filesystem date), not undated/
+    let mut found_file = false;
     for entry in fs::read_dir(target.path()).unwrap() {
         let entry = entry.unwrap();
         if entry.file_type().unwrap().is_dir() {
@@ -319,11 +850,10 @@ fn files_without_metadata_use_filesystem_dates() {
                         let manifest = read_manifest(&month_dir);
                         if let Some(files) = manifest["files"].as_object() {
                             for (_filename, entry) in files {
-                                if let Some(date_source) = entry.get("date_source") {
-                                    if date_source == "filesystem_created" || date_source == "filesystem_modified" {
+                                if let Some(date_source) = entry.get("date_source")
+                                    && (date_source == "filesystem_created" || date_source == "filesystem_modified") {
                                         found_file = true;
                                     }
-                                }
                             }
                         }
                     }
@@ -364,6 +894,25 @@ fn filesystem_dates_preferred_over_undated() {
     assert!(found_in_dated, "file should be in dated folder (YYYY/MM/), not undated/");
 }
 
+#[test]
+fn date_sources_can_leave_out_filesystem_dates() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "screenshot.png", b"plain png bytes");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .arg("--date-sources=quicktime,exif")
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains("0 imported").and(predicate::str::contains("1 undated")));
+
+    let manifest = read_manifest(&target.path().join("undated"));
+    let entry = manifest["files"].as_object().unwrap().values().next().unwrap();
+    assert!(entry["date_source"].is_null());
+}
+
 // --- S8: Unrecognized File Types ---
 
 #[test]
@@ -446,135 +995,1590 @@ fn disappearing_source_does_not_crash() {
         .success();
 }
 
-// --- Manifest Schema ---
+// --- Corrupt Data Detection ---
+
+const MINIMAL_JPEG: &[u8] = &[
+    0xFF, 0xD8, // SOI
+    0xFF, 0xE0, 0x00, 0x04, 0x00, 0x00, // APP0
+    0xFF, 0xDA, 0x00, 0x02, // SOS
+    0x12, 0x34, 0x56, // entropy-coded data
+    0xFF, 0xD9, // EOI
+];
 
 #[test]
-fn manifest_version_is_always_1() {
+fn intact_jpeg_is_imported() {
     let source = TempDir::new().unwrap();
     let target = TempDir::new().unwrap();
 
-    create_file(source.path(), "test.jpg", b"some data");
+    create_file(source.path(), "good.jpg", MINIMAL_JPEG);
 
     cmd()
         .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
         .assert()
-        .success();
-
-    // Find the manifest in dated folder
-    for entry in fs::read_dir(target.path()).unwrap() {
-        let entry = entry.unwrap();
-        if entry.file_type().unwrap().is_dir() {
-            let year_dir = entry.path();
-            if year_dir.file_name().unwrap().to_str().unwrap().chars().all(|c| c.is_numeric()) {
-                for month_entry in fs::read_dir(&year_dir).unwrap() {
-                    let month_dir = month_entry.unwrap().path();
-                    if month_dir.is_dir() {
-                        let manifest = read_manifest(&month_dir);
-                        assert_eq!(manifest["version"], 1);
-                        return;
-                    }
-                }
-            }
-        }
-    }
-    panic!("No manifest found in dated folders");
+        .success()
+        .stdout(predicate::str::contains("1 imported, 0 duplicates, 0 corrupt"));
 }
 
 #[test]
-fn manifest_imported_at_is_utc() {
+fn structurally_corrupt_files_are_quarantined_with_reason() {
     let source = TempDir::new().unwrap();
     let target = TempDir::new().unwrap();
 
-    create_file(source.path(), "test.jpg", b"some data");
+    // JPEG cut off before EOI
+    create_file(source.path(), "truncated.jpg", &MINIMAL_JPEG[..MINIMAL_JPEG.len() - 2]);
+    // PNG whose IHDR chunk claims more data than the file holds
+    create_file(
+        source.path(),
+        "truncated.png",
+        b"\x89PNG\r\n\x1a\n\x00\x00\x00\x0dIHDR\x00\x00",
+    );
+    // MP4 whose mdat box runs past end of file
+    create_file(
+        source.path(),
+        "truncated.mp4",
+        b"\x00\x00\x00\x10ftypisom\x00\x00\x02\x00\x00\x00\x10\x00mdat\x00\x00",
+    );
+    create_file(source.path(), "empty.heic", b"");
 
     cmd()
         .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
         .assert()
-        .success();
+        .code(3)
+        .stdout(predicate::str::contains("0 imported, 0 duplicates, 4 corrupt"))
+        .stderr(predicate::str::contains("missing EOI marker"));
+
+    let corrupt_manifest = read_manifest(&target.path().join("corrupt"));
+    let files = corrupt_manifest["files"].as_object().expect("files is object");
+    assert_eq!(files.len(), 4, "all corrupt files should be recorded");
+    for name in ["truncated.jpg", "truncated.png", "truncated.mp4", "empty.heic"] {
+        assert!(target.path().join("corrupt").join(name).exists(), "{} must be quarantined", name);
+        let reason = files[name]["corrupt_reason"].as_str().expect("corrupt_reason recorded");
+        assert!(!reason.is_empty());
+        assert_eq!(files[name]["error_kind"], "invalid_data");
+        assert!(files[name]["hash"].is_string(), "validated files were hashed");
+    }
+    assert_eq!(files["empty.heic"]["corrupt_reason"], "zero-length file");
+}
 
-    // Find the manifest in dated folder
-    for entry in fs::read_dir(target.path()).unwrap() {
-        let entry = entry.unwrap();
-        if entry.file_type().unwrap().is_dir() {
-            let year_dir = entry.path();
-            if year_dir.file_name().unwrap().to_str().unwrap().chars().all(|c| c.is_numeric()) {
-                for month_entry in fs::read_dir(&year_dir).unwrap() {
-                    let month_dir = month_entry.unwrap().path();
-                    if month_dir.is_dir() {
-                        let manifest = read_manifest(&month_dir);
-                        if let Some(files) = manifest["files"].as_object() {
-                            if let Some(entry) = files.values().next() {
-                                let imported_at = entry["imported_at"].as_str().unwrap();
-                                assert!(imported_at.ends_with('Z'), "imported_at must end with Z (UTC)");
-                                assert!(imported_at.contains('T'), "imported_at must be ISO 8601");
-                                return;
-                            }
-                        }
-                    }
+#[test]
+fn quarantined_files_are_never_canonical() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let truncated = &MINIMAL_JPEG[..MINIMAL_JPEG.len() - 2];
+    create_file(source.path(), "a/truncated.jpg", truncated);
+    create_file(source.path(), "b/truncated.jpg", truncated);
+
+    for _ in 0..2 {
+        cmd()
+            .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+            .assert()
+            .code(3)
+            .stdout(predicate::str::contains("0 imported, 0 duplicates, 2 corrupt"));
+    }
+
+    // The second copy and every re-import point at the one quarantined file
+    let corrupt_manifest = read_manifest(&target.path().join("corrupt"));
+    assert_eq!(corrupt_manifest["files"].as_object().unwrap().len(), 1);
+    assert!(!target.path().join("duplicates").exists(), "corrupt files must not be canonical");
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .code(3)
+        .stderr(predicate::str::contains("already quarantined as corrupt/truncated.jpg"));
+}
+
+#[test]
+fn unhashed_corrupt_entries_are_not_indexed_for_dedup() {
+    let target = TempDir::new().unwrap();
+    let corrupt_dir = target.path().join("corrupt");
+    create_file(
+        &corrupt_dir,
+        ".manifest.json",
+        br#"{
+            "version": 1,
+            "files": {
+                "unreadable.jpg": {
+                    "original_path": "/source/unreadable.jpg",
+                    "original_name": "unreadable.jpg",
+                    "error_kind": "permission_denied",
+                    "corrupt_reason": "Permission denied (os error 13)",
+                    "imported_at": "2026-01-01T00:00:00Z",
+                    "file_size_bytes": 0
                 }
             }
-        }
-    }
-    panic!("No manifest with files found in dated folders");
+        }"#,
+    );
+
+    let index = image_organiser::manifest::build_dedup_index(target.path(), &|_| {});
+    assert!(index.get(HashAlgorithm::Sha256, "").is_none(), "entries without a hash must not enter the dedup index");
+    assert!(!index.contains_size(0), "entries without a hash must not enter the size prefilter");
 }
 
-// --- Summary Format ---
+#[test]
+fn dry_run_reports_corrupt_without_quarantining() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "empty.jpg", b"");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .code(3)
+        .stdout(predicate::str::contains("1 corrupt"))
+        .stderr(predicate::str::contains("[DRY RUN] CORRUPT:"));
+
+    assert!(!target.path().join("corrupt").exists(), "dry-run must not quarantine");
+}
+
+// --- Source Hash Cache ---
 
 #[test]
-fn summary_format_matches_spec() {
+fn hash_cache_is_consulted_until_disabled_or_cleared() {
     let source = TempDir::new().unwrap();
     let target = TempDir::new().unwrap();
 
-    create_file(source.path(), "a.jpg", b"data a");
-    create_file(source.path(), "b.txt", b"data b");
+    create_file(source.path(), "photo.jpg", b"original bytes");
 
-    let output = cmd()
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    assert!(target.path().join(".hash-cache.json").exists(), "execute must persist the hash cache");
+
+    // Rewrite in place with the same size and mtime: only the cache still
+    // believes this is the imported file
+    let photo = source.path().join("photo.jpg");
+    let mtime = fs::metadata(&photo).unwrap().modified().unwrap();
+    fs::write(&photo, b"changed bytes!").unwrap();
+    fs::File::options().write(true).open(&photo).unwrap().set_modified(mtime).unwrap();
+
+    cmd()
         .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
-        .output()
-        .expect("run command");
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 imported, 1 duplicates"));
 
-    let stdout = String::from_utf8_lossy(&output.stdout);
-    let re = regex_lite::Regex::new(
-        r"^\[DRY RUN\] \d+ imported, \d+ duplicates, \d+ corrupt, \d+ undated, \d+ skipped$"
-    ).unwrap();
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--no-cache"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 imported, 0 duplicates"));
+
+    cmd()
+        .args(["cache", "clear", target.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("Cleared hash cache"));
+    assert!(!target.path().join(".hash-cache.json").exists());
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 imported, 0 duplicates"));
+}
+
+/// Bytes this process has read so far, from `/proc/self/io`.
+fn bytes_read() -> u64 {
+    let io = fs::read_to_string("/proc/self/io").unwrap();
+    io.lines()
+        .find_map(|line| line.strip_prefix("rchar: "))
+        .and_then(|n| n.parse().ok())
+        .unwrap()
+}
+
+#[test]
+fn cached_duplicates_are_not_read() {
+    use image_organiser::import::{DuplicatePolicy, Importer, Silent};
+
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let size = 8 * 1024 * 1024;
+    create_file(source.path(), "video.mov", &vec![7u8; size]);
+    Importer::new(source.path(), target.path()).execute(true).run(&Silent).unwrap();
+
+    let second_source = TempDir::new().unwrap();
+    fs::copy(source.path().join("video.mov"), second_source.path().join("copy.mov")).unwrap();
+    let importer = Importer::new(second_source.path(), target.path())
+        .execute(true)
+        .duplicates(DuplicatePolicy::Record);
+    // The first run hashes the copy and caches it
+    assert_eq!(importer.run(&Silent).unwrap().summary.duplicates, 1);
+
+    let before = bytes_read();
+    let outcome = importer.run(&Silent).unwrap();
+    assert_eq!(outcome.summary.duplicates, 1);
     assert!(
-        stdout.lines().any(|line| re.is_match(line)),
-        "summary line must match spec format, got: {}", stdout
+        bytes_read() - before < size as u64 / 2,
+        "a duplicate known from the cache must not be read again"
     );
 }
 
-// --- Extension Coverage ---
-
 #[test]
-fn all_30_spec_extensions_are_recognized() {
-    let extensions = [
-        "heic", "heif", "jpeg", "jpg", "png", "tiff", "tif", "webp", "bmp", "gif", "avif",
-        "cr2", "cr3", "nef", "arw", "raf", "rw2", "dng", "orf", "pef", "srw", "3fr",
-        "mov", "mp4", "m4v", "avi", "mkv", "3gp",
-        "aae",
-    ];
+fn stale_hash_is_replaced_by_the_hash_of_the_copied_bytes() {
+    use std::os::unix::fs::MetadataExt;
 
-    for ext in &extensions {
-        let path = std::path::PathBuf::from(format!("test.{}", ext));
-        match image_organiser::scan::classify_file(&path) {
-            image_organiser::scan::MediaFile::Recognized { .. } => {}
-            image_organiser::scan::MediaFile::Unrecognized { .. } => {
-                panic!(".{} must be recognized per spec", ext);
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    // Same size, so both are hashed before the copy
+    create_file(source.path(), "a.jpg", b"content a");
+    create_file(source.path(), "b.jpg", b"content b");
+
+    // A cache entry that no longer matches the bytes on disk
+    let photo = std::path::absolute(source.path().join("a.jpg")).unwrap();
+    let metadata = fs::metadata(&photo).unwrap();
+    let mtime_ns = metadata.modified().unwrap().duration_since(std::time::UNIX_EPOCH).unwrap().as_nanos();
+    let cache = serde_json::json!({
+        "version": 1,
+        "entries": {
+            photo.to_str().unwrap(): {
+                "size": metadata.len(),
+                "mtime_ns": mtime_ns as u64,
+                "inode": metadata.ino(),
+                "sha256": "00".repeat(32),
             }
         }
-    }
+    });
+    fs::write(target.path().join(".hash-cache.json"), cache.to_string()).unwrap();
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 imported"))
+        .stderr(predicate::str::contains("Source changed during import"));
+
+    let manifest = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .find(|e| e.file_name() == ".manifest.json")
+        .map(|e| read_manifest(e.path().parent().unwrap()))
+        .unwrap();
+    let entry = manifest["files"]
+        .as_object()
+        .unwrap()
+        .values()
+        .find(|e| e["original_name"] == "a.jpg")
+        .unwrap();
+    assert_eq!(entry["hash"], sha256_hex(&photo), "manifest must record the hash of the bytes written");
 }
 
 #[test]
-fn case_insensitive_extensions() {
-    let cases = ["JPG", "Heic", "MOV", "Png", "AAE"];
-    for ext in &cases {
-        let path = std::path::PathBuf::from(format!("test.{}", ext));
-        match image_organiser::scan::classify_file(&path) {
-            image_organiser::scan::MediaFile::Recognized { .. } => {}
-            image_organiser::scan::MediaFile::Unrecognized { .. } => {
-                panic!(".{} must be recognized (case insensitive)", ext);
-            }
-        }
-    }
+fn hash_of_a_file_changed_after_reading_is_not_cached() {
+    use image_organiser::cache::{self, HashCache};
+
+    let dir = TempDir::new().unwrap();
+    create_file(dir.path(), "photo.jpg", b"first bytes");
+    let photo = dir.path().join("photo.jpg");
+
+    // Fingerprint taken before the read, then the file is replaced
+    let before = cache::fingerprint(&photo).unwrap();
+    let hash = sha256_hex(&photo);
+    fs::remove_file(&photo).unwrap();
+    create_file(dir.path(), "photo.jpg", b"other, longer bytes");
+
+    let mut hash_cache = HashCache::default();
+    hash_cache.record(&photo, before, HashAlgorithm::Sha256, &hash);
+    let now = cache::fingerprint(&photo).unwrap();
+    assert!(
+        hash_cache.lookup(&photo, now, &[HashAlgorithm::Sha256]).is_none(),
+        "a hash read from the old file must not be cached for the new one"
+    );
+
+    hash_cache.record(&photo, now, HashAlgorithm::Sha256, &sha256_hex(&photo));
+    assert!(hash_cache.lookup(&photo, now, &[HashAlgorithm::Sha256]).is_some());
+}
+
+#[test]
+fn no_cache_does_not_write_cache() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "photo.jpg", b"some bytes");
+
+    cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--no-cache",
+        ])
+        .assert()
+        .success();
+    assert!(!target.path().join(".hash-cache.json").exists());
+}
+
+// --- Library Index ---
+
+#[test]
+fn library_index_tracks_manifests() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "photo.jpg", b"indexed content");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    assert!(target.path().join(".library.db").exists(), "execute must create the library index");
+
+    let hash = sha256_hex(&source.path().join("photo.jpg"));
+    let index = image_organiser::index::LibraryIndex::open_read_only(target.path()).unwrap();
+    assert!(index.is_fresh().unwrap());
+    let indexed = index.dedup_index().unwrap();
+    let path = indexed.get(HashAlgorithm::Sha256, &hash).expect("imported file must be indexed");
+    assert!(path.exists());
+
+    // Emptying the manifest by hand makes the index stale until refreshed
+    let month_dir = path.parent().unwrap();
+    fs::write(month_dir.join(".manifest.json"), br#"{"version": 1, "files": {}}"#).unwrap();
+    assert!(!index.is_fresh().unwrap());
+
+    let mut index = image_organiser::index::LibraryIndex::open(target.path()).unwrap();
+    assert_eq!(index.refresh(&|_| {}).unwrap(), 1);
+    assert!(index.dedup_index().unwrap().get(HashAlgorithm::Sha256, &hash).is_none());
+    assert!(index.is_fresh().unwrap());
+
+    // A folder added deep inside an unchanged tree is still found
+    let added = target.path().join("2019/07");
+    fs::create_dir_all(&added).unwrap();
+    fs::copy(month_dir.join(".manifest.json"), added.join(".manifest.json")).unwrap();
+    assert!(!index.is_fresh().unwrap());
+    assert_eq!(index.refresh(&|_| {}).unwrap(), 1);
+    assert!(index.is_fresh().unwrap());
+}
+
+#[test]
+fn missing_library_index_is_rebuilt_from_manifests() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "photo.jpg", b"rebuild me");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    fs::remove_file(target.path().join(".library.db")).unwrap();
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 duplicates"));
+    assert!(!target.path().join(".library.db").exists(), "dry-run must not create the index");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 duplicates"));
+    assert!(target.path().join(".library.db").exists());
+}
+
+// --- Hash Algorithms ---
+
+#[test]
+fn blake3_import_records_algorithm() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "photo.png", b"blake3 content");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute", "--hash=blake3"])
+        .assert()
+        .success();
+
+    let digests = image_organiser::metadata::hash_file(&source.path().join("photo.png"), &[HashAlgorithm::Blake3]).unwrap();
+    let expected = image_organiser::metadata::format_hash(&digests.blake3.unwrap());
+    let manifest_dir = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .find(|e| e.file_name() == ".manifest.json")
+        .map(|e| e.path().parent().unwrap().to_path_buf())
+        .expect("manifest written");
+    let manifest = read_manifest(&manifest_dir);
+    let entry = manifest["files"].as_object().unwrap().values().next().unwrap();
+    assert_eq!(entry["hash_algorithm"], "blake3");
+    assert_eq!(entry["hash"], expected.as_str());
+}
+
+#[test]
+fn mixed_algorithm_library_still_detects_duplicates() {
+    let first = TempDir::new().unwrap();
+    let second = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(first.path(), "photo.png", b"shared content");
+    create_file(second.path(), "copy.png", b"shared content");
+
+    cmd()
+        .args(["import", first.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    cmd()
+        .args(["import", second.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute", "--hash=blake3"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 imported, 1 duplicates"));
+
+    let manifest = read_manifest(&target.path().join("duplicates"));
+    let entry = manifest["files"].as_object().unwrap().values().next().unwrap();
+    assert_eq!(entry["hash_algorithm"], "blake3");
+}
+
+#[test]
+fn version_1_manifest_is_read_as_sha256() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "photo.png", b"legacy content");
+    let hash = sha256_hex(&source.path().join("photo.png"));
+    let manifest = format!(
+        r#"{{
+            "version": 1,
+            "files": {{
+                "photo_abcd.png": {{
+                    "sha256": "{}",
+                    "original_path": "/old/photo.png",
+                    "original_name": "photo.png",
+                    "imported_at": "2026-01-01T00:00:00Z",
+                    "file_size_bytes": 14
+                }}
+            }}
+        }}"#,
+        hash
+    );
+    create_file(&target.path().join("undated"), ".manifest.json", manifest.as_bytes());
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute", "--hash=blake3"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 duplicates"));
+
+    let manifest = read_manifest(&target.path().join("duplicates"));
+    let entry = manifest["files"].as_object().unwrap().values().next().unwrap();
+    assert_eq!(entry["duplicate_of"], "undated/photo_abcd.png");
+}
+
+// --- Concurrency ---
+
+#[test]
+fn serial_jobs_import_everything() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    for i in 0..6 {
+        create_file(source.path(), &format!("img_{}.png", i), format!("content {}", i).as_bytes());
+    }
+    create_file(source.path(), "copy.png", b"content 0");
+
+    cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--jobs=1",
+            "--copy-jobs=1",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("6 imported, 1 duplicates"));
+}
+
+#[test]
+fn jobs_must_be_positive_or_auto() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--jobs=0"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("positive number or `auto`"));
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--jobs=auto", "--copy-jobs=4"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn copy_jobs_cannot_exceed_jobs() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"photo a");
+
+    cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--jobs=2",
+            "--copy-jobs=3",
+        ])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("--copy-jobs=3 exceeds --jobs=2"));
+    assert!(!target.path().join(".manifest.json").exists());
+    assert_eq!(std::fs::read_dir(target.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn rotational_flag_follows_sysfs_device_links() {
+    use image_organiser::concurrency::{device_numbers, rotational_flag};
+    use std::os::unix::fs::symlink;
+
+    let sys = TempDir::new().unwrap();
+    let devices = sys.path().join("devices");
+    // A spinning disk with one partition, and an SSD used whole
+    std::fs::create_dir_all(devices.join("sda/sda1")).unwrap();
+    create_file(&devices.join("sda/queue"), "rotational", b"1\n");
+    std::fs::create_dir_all(devices.join("nvme0n1")).unwrap();
+    create_file(&devices.join("nvme0n1/queue"), "rotational", b"0\n");
+    let block = sys.path().join("dev/block");
+    std::fs::create_dir_all(&block).unwrap();
+    symlink("../../devices/sda", block.join("8:0")).unwrap();
+    symlink("../../devices/sda/sda1", block.join("8:1")).unwrap();
+    symlink("../../devices/nvme0n1", block.join("259:0")).unwrap();
+
+    assert_eq!(rotational_flag(&block, 8, 0), Some(true));
+    assert_eq!(rotational_flag(&block, 8, 1), Some(true), "partition falls back to its disk's queue");
+    assert_eq!(rotational_flag(&block, 259, 0), Some(false));
+    assert_eq!(rotational_flag(&block, 0, 42), None, "no such device");
+
+    assert_eq!(device_numbers(0x0801), (8, 1));
+    assert_eq!(device_numbers(0x10300), (259, 0));
+    // Minors above 255 and majors above 4095 use the high bits
+    assert_eq!(device_numbers(0x1000_0012_34ff), (0x1234, 0x1ff));
+}
+
+#[test]
+fn concurrent_imports_into_one_target_never_lose_manifest_entries() {
+    let target = TempDir::new().unwrap();
+    let sources: Vec<TempDir> = (0..2).map(|_| TempDir::new().unwrap()).collect();
+    for (s, source) in sources.iter().enumerate() {
+        for i in 0..200 {
+            create_file(source.path(), &format!("img_{:03}.png", i), format!("run {} image {}", s, i).as_bytes());
+        }
+    }
+
+    let runs: Vec<_> = sources
+        .iter()
+        .map(|source| {
+            let mut command = std::process::Command::new(assert_cmd::cargo::cargo_bin!("image-organiser"));
+            command
+                .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+                .args(["--execute", "--jobs=1", "--quiet"])
+                .stdout(std::process::Stdio::null())
+                .stderr(std::process::Stdio::piped());
+            command.spawn().unwrap()
+        })
+        .collect();
+    let outputs: Vec<_> = runs.into_iter().map(|run| run.wait_with_output().unwrap()).collect();
+
+    let succeeded = outputs.iter().filter(|o| o.status.success()).count();
+    assert!(succeeded >= 1);
+    for output in outputs.iter().filter(|o| !o.status.success()) {
+        assert_eq!(output.status.code(), Some(1));
+        assert!(String::from_utf8_lossy(&output.stderr).contains("in use by another import"));
+    }
+    let mut files = 0;
+    let mut entries = 0;
+    for entry in walkdir::WalkDir::new(target.path()).into_iter().flatten() {
+        if entry.path().extension().is_some_and(|ext| ext == "png") {
+            files += 1;
+        } else if entry.file_name() == ".manifest.json" {
+            entries += read_manifest(entry.path().parent().unwrap())["files"].as_object().unwrap().len();
+        }
+    }
+    assert_eq!(files, 200 * succeeded);
+    assert_eq!(entries, files, "every library file is listed in a manifest");
+}
+
+#[test]
+fn max_bandwidth_limits_copy_rate() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", &vec![1u8; 300 * 1024]);
+    create_file(source.path(), "b.png", &vec![2u8; 310 * 1024]);
+
+    let started = std::time::Instant::now();
+    cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--max-bandwidth=1MiB/s",
+        ])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("2 imported"));
+    assert!(
+        started.elapsed() >= std::time::Duration::from_millis(500),
+        "610 KiB at 1 MiB/s must take over half a second, took {:?}",
+        started.elapsed()
+    );
+}
+
+#[test]
+fn max_bandwidth_rejects_unknown_units() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--max-bandwidth=50 furlongs"])
+        .assert()
+        .failure()
+        .stderr(predicate::str::contains("expected a rate like"));
+}
+
+// --- Exit Codes ---
+
+#[test]
+fn clean_run_exits_zero_and_corrupt_files_exit_three() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.png", b"photo a");
+    create_file(source.path(), "notes.txt", b"text");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .code(0);
+    // Duplicates alone are not a problem
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .code(0)
+        .stdout(predicate::str::contains("1 duplicates"));
+
+    create_file(source.path(), "empty.jpg", b"");
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .code(3);
+}
+
+#[cfg(unix)]
+#[test]
+fn interrupted_run_exits_130_and_records_finished_files() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    for i in 0..6 {
+        create_file(source.path(), &format!("clip_{}.png", i), &vec![i as u8; 512 * 1024 + i]);
+    }
+
+    let child = std::process::Command::new(assert_cmd::cargo::cargo_bin!("image-organiser"))
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--jobs=1",
+            "--max-bandwidth=1MiB/s",
+        ])
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::null())
+        .spawn()
+        .expect("start import");
+    std::thread::sleep(std::time::Duration::from_millis(1200));
+    std::process::Command::new("kill")
+        .args(["-INT", &child.id().to_string()])
+        .status()
+        .expect("send SIGINT");
+    let output = child.wait_with_output().expect("wait for import");
+
+    assert_eq!(output.status.code(), Some(130));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let imported: usize = stdout.split(" imported").next().unwrap().trim().parse().expect("summary printed");
+    assert!((1..6).contains(&imported), "some but not all files imported, got: {}", stdout);
+    let recorded = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .filter(|e| e.file_name() == ".manifest.json")
+        .map(|e| read_manifest(e.path().parent().unwrap())["files"].as_object().unwrap().len())
+        .sum::<usize>();
+    assert_eq!(recorded, imported, "finished files must be in the manifest");
+}
+
+// --- Free Space ---
+
+#[test]
+fn dry_run_reports_projected_bytes() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.png", &[1u8; 3000]);
+    create_file(source.path(), "b.png", &[1u8; 3000]);
+    create_file(source.path(), "c.png", &[2u8; 1000]);
+
+    // The duplicate is only written under --duplicates=copy
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("[DRY RUN] 6.84 KiB would be written to the target"));
+    let output = cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--duplicates=skip",
+            "--output=jsonl",
+        ])
+        .output()
+        .expect("run command");
+    let summary: serde_json::Value =
+        serde_json::from_str(String::from_utf8_lossy(&output.stdout).lines().last().unwrap()).unwrap();
+    assert_eq!(summary["projected_bytes"], 4000);
+}
+
+#[test]
+fn full_target_stops_the_run_and_removes_partial_copies() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    for i in 0..5u8 {
+        create_file(source.path(), &format!("{}.png", i), &[i; 1000]);
+    }
+
+    // Room for two files: the third copy fails and the rest are not started
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .args(["--execute", "--jobs=1", "--simulate-free-space=2500", "--force"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("Not enough space in"))
+        .stderr(predicate::str::contains("this import needs 4.88 KiB, 2.44 KiB available"))
+        .stderr(predicate::str::contains("Target disk full, stopping after files in progress"))
+        .stderr(predicate::str::contains("3 files were not imported; free up to 2.93 KiB"));
+
+    let library: Vec<_> = walkdir::WalkDir::new(target.path()).into_iter().flatten().collect();
+    assert!(
+        !library.iter().any(|e| e.file_name().to_string_lossy().ends_with(".partial")),
+        "partial copies must be removed"
+    );
+    let imported = library.iter().filter(|e| e.path().extension().is_some_and(|ext| ext == "png")).count();
+    assert_eq!(imported, 2);
+    assert_eq!(fs::read_dir(source.path()).unwrap().count(), 5, "sources are kept");
+}
+
+#[test]
+fn full_target_reports_the_files_left() {
+    use image_organiser::concurrency::Jobs;
+    use image_organiser::import::{Importer, Silent, Stopped};
+
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    for i in 0..5u8 {
+        create_file(source.path(), &format!("{}.png", i), &[i; 1000]);
+    }
+    let outcome = Importer::new(source.path(), target.path())
+        .execute(true)
+        .jobs(Jobs::Fixed(std::num::NonZeroUsize::MIN))
+        .simulate_free_space(Some(2500))
+        .force(true)
+        .run(&Silent)
+        .unwrap();
+    assert_eq!(outcome.summary.imported, 2);
+    assert_eq!(
+        outcome.stopped,
+        Some(Stopped::DiskFull {
+            remaining_files: 3,
+            remaining_bytes: 3000
+        })
+    );
+}
+
+#[test]
+fn too_small_target_is_refused_before_anything_is_written() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", &[1u8; 3000]);
+    create_file(source.path(), "b.png", &[2u8; 1000]);
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .args(["--execute", "--simulate-free-space=3000"])
+        .assert()
+        .code(4)
+        .stderr(predicate::str::contains("ERROR: Not enough space in"))
+        .stderr(predicate::str::contains("this import needs 3.91 KiB, 2.93 KiB available (pass --force to start anyway)"));
+    let written = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .any(|e| e.file_name() == ".manifest.json" || e.path().extension().is_some_and(|ext| ext == "png"));
+    assert!(!written, "a refused import writes nothing");
+
+    // Dry runs only report what would be written
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .arg("--simulate-free-space=0")
+        .assert()
+        .success();
+}
+
+#[test]
+fn insufficient_space_error_reports_needed_and_available_bytes() {
+    use image_organiser::import::{ImportError, Importer, Silent};
+
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", &[1u8; 3000]);
+    let importer = Importer::new(source.path(), target.path())
+        .execute(true)
+        .simulate_free_space(Some(2000));
+    assert!(matches!(
+        importer.run(&Silent),
+        Err(ImportError::InsufficientSpace {
+            needed: 3000,
+            available: 2000
+        })
+    ));
+    let outcome = importer.force(true).simulate_free_space(Some(3000)).run(&Silent).unwrap();
+    assert_eq!(outcome.summary.imported, 1);
+    assert_eq!(outcome.stopped, None);
+}
+
+// --- Manifest Schema ---
+
+#[test]
+fn manifest_version_is_current() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "test.jpg", b"some data");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    // Find the manifest in dated folder
+    for entry in fs::read_dir(target.path()).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            let year_dir = entry.path();
+            if year_dir.file_name().unwrap().to_str().unwrap().chars().all(|c| c.is_numeric()) {
+                for month_entry in fs::read_dir(&year_dir).unwrap() {
+                    let month_dir = month_entry.unwrap().path();
+                    if month_dir.is_dir() {
+                        let manifest = read_manifest(&month_dir);
+                        assert_eq!(manifest["version"], 2);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+    panic!("No manifest found in dated folders");
+}
+
+#[test]
+fn manifest_imported_at_is_utc() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "test.jpg", b"some data");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    // Find the manifest in dated folder
+    for entry in fs::read_dir(target.path()).unwrap() {
+        let entry = entry.unwrap();
+        if entry.file_type().unwrap().is_dir() {
+            let year_dir = entry.path();
+            if year_dir.file_name().unwrap().to_str().unwrap().chars().all(|c| c.is_numeric()) {
+                for month_entry in fs::read_dir(&year_dir).unwrap() {
+                    let month_dir = month_entry.unwrap().path();
+                    if month_dir.is_dir() {
+                        let manifest = read_manifest(&month_dir);
+                        if let Some(files) = manifest["files"].as_object()
+                            && let Some(entry) = files.values().next() {
+                                let imported_at = entry["imported_at"].as_str().unwrap();
+                                assert!(imported_at.ends_with('Z'), "imported_at must end with Z (UTC)");
+                                assert!(imported_at.contains('T'), "imported_at must be ISO 8601");
+                                return;
+                            }
+                    }
+                }
+            }
+        }
+    }
+    panic!("No manifest with files found in dated folders");
+}
+
+// --- Summary Format ---
+
+#[test]
+fn summary_format_matches_spec() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.jpg", b"data a");
+    create_file(source.path(), "b.txt", b"data b");
+
+    let output = cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .output()
+        .expect("run command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let re = regex_lite::Regex::new(
+        r"^\[DRY RUN\] \d+ imported, \d+ duplicates, \d+ corrupt, \d+ undated, \d+ skipped$"
+    ).unwrap();
+    assert!(
+        stdout.lines().any(|line| re.is_match(line)),
+        "summary line must match spec format, got: {}", stdout
+    );
+}
+
+// --- Structured Output ---
+
+#[test]
+fn jsonl_output_reports_every_file_and_a_summary() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.png", b"same content");
+    create_file(source.path(), "b.png", b"same content");
+    create_file(source.path(), "broken.jpg", &MINIMAL_JPEG[..MINIMAL_JPEG.len() - 2]);
+    create_file(source.path(), "notes.txt", b"text");
+
+    let output = cmd()
+        .args([
+            "import",
+            source.path().to_str().unwrap(),
+            target.path().to_str().unwrap(),
+            "--execute",
+            "--output=jsonl",
+        ])
+        .output()
+        .expect("run command");
+    assert_eq!(output.status.code(), Some(3), "corrupt files need attention");
+
+    let events: Vec<serde_json::Value> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| serde_json::from_str(line).expect("every stdout line is JSON"))
+        .collect();
+    let file = |name: &str| {
+        events
+            .iter()
+            .find(|e| e["event"] == "file" && e["source"].as_str().unwrap().ends_with(name))
+            .unwrap_or_else(|| panic!("no event for {}", name))
+    };
+
+    let imported = file("a.png");
+    assert_eq!(imported["category"], "imported");
+    assert_eq!(imported["hash"], sha256_hex(&source.path().join("a.png")));
+    assert_eq!(imported["hash_algorithm"], "sha256");
+    assert_eq!(imported["date_source"], "filesystem_created");
+    assert!(imported["date"].is_string());
+    assert!(Path::new(imported["destination"].as_str().unwrap()).exists());
+
+    let duplicate = file("b.png");
+    assert_eq!(duplicate["category"], "duplicate");
+    assert_eq!(duplicate["duplicate_of"], imported["destination"]);
+    assert_eq!(duplicate["hash"], imported["hash"]);
+
+    let corrupt = file("broken.jpg");
+    assert_eq!(corrupt["category"], "corrupt");
+    assert!(corrupt["error"].as_str().unwrap().contains("missing EOI marker"));
+
+    let skipped = file("notes.txt");
+    assert_eq!(skipped["category"], "skipped");
+    assert_eq!(skipped["destination"], serde_json::Value::Null);
+
+    let summary = events.last().expect("summary event");
+    assert_eq!(summary["event"], "summary");
+    assert_eq!(summary["schema_version"], 1);
+    assert_eq!(summary["imported"], 1);
+    assert_eq!(summary["duplicates"], 1);
+    assert_eq!(summary["corrupt"], 1);
+    assert_eq!(summary["skipped"], 1);
+    assert_eq!(summary["dry_run"], false);
+    assert_eq!(events.len(), 5);
+}
+
+// --- Import Reports ---
+
+#[test]
+fn execute_writes_run_report() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.png", b"photo a");
+    create_file(source.path(), "notes.txt", b"text");
+
+    cmd()
+        .env("SOURCE_DATE_EPOCH", "1700000000")
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    let run_dir = target.path().join(".imports").join("20231114T221320Z");
+    let report: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(run_dir.join("report.json")).expect("report.json written"))
+            .expect("report is JSON");
+    assert_eq!(report["version"], env!("CARGO_PKG_VERSION"));
+    assert!(report["command_line"].as_array().unwrap().iter().any(|arg| arg == "--execute"));
+    assert_eq!(report["summary"]["imported"], 1);
+    assert_eq!(report["summary"]["skipped"], 1);
+    let files = report["files"].as_array().unwrap();
+    assert_eq!(files.len(), 2);
+    let imported = files.iter().find(|f| f["category"] == "imported").expect("imported file listed");
+    assert_eq!(imported["date_source"], "filesystem_created");
+    assert!(Path::new(imported["destination"].as_str().unwrap()).exists());
+
+    let text = fs::read_to_string(run_dir.join("report.txt")).expect("report.txt written");
+    assert!(text.contains("1 imported, 0 duplicates, 0 corrupt, 0 undated, 1 skipped"));
+    assert!(text.contains("skipped") && text.contains("notes.txt"));
+
+    // A second run in the same second gets its own directory
+    cmd()
+        .env("SOURCE_DATE_EPOCH", "1700000000")
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    assert!(target.path().join(".imports/20231114T221320Z-2/report.json").exists());
+}
+
+#[test]
+fn dry_run_writes_no_report() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.png", b"photo a");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(!target.path().join(".imports").exists());
+}
+
+// --- Config Files ---
+
+#[test]
+fn project_config_sets_layout_naming_and_duplicate_policy() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let user_config = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.png", b"image a");
+    create_file(source.path(), "b.png", b"image a");
+    create_file(source.path(), "cache/c.png", b"image c");
+    create_file(
+        target.path(),
+        ".image-organiser.toml",
+        b"layout = \"{year}\"\nnaming = \"IMG_{year}{month}{day}\"\nduplicates = \"skip\"\nignore = [\"cache/\"]\n",
+    );
+
+    cmd()
+        .env("XDG_CONFIG_HOME", user_config.path())
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 imported, 1 duplicates, 0 corrupt, 0 undated, 0 skipped"));
+
+    let year_dir = fs::read_dir(target.path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .find(|p| p.file_name().unwrap().to_str().unwrap().chars().all(|c| c.is_ascii_digit()))
+        .expect("year directory");
+    let manifest = read_manifest(&year_dir);
+    let names: Vec<&String> = manifest["files"].as_object().unwrap().keys().collect();
+    assert_eq!(names.len(), 1);
+    assert!(names[0].starts_with("IMG_") && names[0].len() == "IMG_20240115.png".len(), "{}", names[0]);
+    assert!(!target.path().join("duplicates").exists());
+
+    // A flag overrides the config file
+    let second = TempDir::new().unwrap();
+    create_file(second.path(), "d.png", b"image a");
+    cmd()
+        .env("XDG_CONFIG_HOME", user_config.path())
+        .args(["import", second.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .arg("--duplicates=record")
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("recorded"));
+}
+
+#[test]
+fn config_show_merges_user_and_project_config() {
+    let target = TempDir::new().unwrap();
+    let user_config = TempDir::new().unwrap();
+
+    create_file(
+        user_config.path(),
+        "image-organiser/config.toml",
+        b"duplicates = \"record\"\ndate_sources = [\"exif\"]\nignore = [\"*.tmp\"]\n",
+    );
+    create_file(
+        target.path(),
+        ".image-organiser.toml",
+        b"date_sources = [\"quicktime\", \"exif\"]\nignore = [\"scratch/\"]\nextensions = [\".JPG\"]\n",
+    );
+
+    cmd()
+        .env("XDG_CONFIG_HOME", user_config.path())
+        .args(["config", "show", target.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("duplicates = \"record\""))
+        .stdout(predicate::str::contains("date_sources = [\"quicktime\", \"exif\"]"))
+        .stdout(predicate::str::contains("ignore = [\"*.tmp\", \"scratch/\"]"))
+        .stdout(predicate::str::contains("extensions = [\"jpg\"]"))
+        .stdout(predicate::str::contains("layout = \"{year}/{month}\""));
+}
+
+#[test]
+fn invalid_config_stops_the_import() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    let user_config = TempDir::new().unwrap();
+
+    create_file(source.path(), "a.png", b"image a");
+    create_file(target.path(), ".image-organiser.toml", b"layout = \"{year}/{week}\"\n");
+
+    cmd()
+        .env("XDG_CONFIG_HOME", user_config.path())
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("unknown placeholder {week}"));
+}
+
+// --- Library Settings ---
+
+#[test]
+fn first_import_binds_layout_and_later_flags_must_match() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"image a");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .arg("--layout={year}")
+        .assert()
+        .success();
+    let settings = fs::read_to_string(target.path().join(".library.toml")).unwrap();
+    assert!(settings.contains("layout = \"{year}\""), "{}", settings);
+
+    // Without the flag the bound layout is used
+    create_file(source.path(), "b.png", b"image b");
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    let year_dirs: Vec<_> = fs::read_dir(target.path())
+        .unwrap()
+        .map(|e| e.unwrap().path())
+        .filter(|p| p.file_name().unwrap().to_str().unwrap().chars().all(|c| c.is_ascii_digit()))
+        .collect();
+    assert_eq!(year_dirs.len(), 1);
+    assert_eq!(read_manifest(&year_dirs[0])["files"].as_object().unwrap().len(), 2);
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .arg("--layout={year}/{month}")
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("conflicts with").and(predicate::str::contains("library migrate")));
+}
+
+#[test]
+fn existing_library_without_settings_is_bound_to_the_defaults() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"image a");
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+    // A library imported into before settings were recorded
+    fs::remove_file(target.path().join(".library.toml")).unwrap();
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--layout={year}"])
+        .assert()
+        .code(2)
+        .stderr(predicate::str::contains("conflicts with \"{year}/{month}\"").and(predicate::str::contains("library migrate")));
+
+    // A config file cannot rebind it either; the defaults stay in force
+    fs::write(target.path().join(".image-organiser.toml"), "layout = \"{year}\"\n").unwrap();
+    create_file(source.path(), "b.png", b"image b");
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("1 imported"));
+    let settings = fs::read_to_string(target.path().join(".library.toml")).unwrap();
+    assert!(settings.contains("layout = \"{year}/{month}\""), "{}", settings);
+    let pngs: Vec<_> = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .flatten()
+        .filter(|e| e.path().extension().is_some_and(|ext| ext == "png"))
+        .filter(|e| !e.path().starts_with(target.path().join("duplicates")))
+        .map(|e| e.depth())
+        .collect();
+    assert_eq!(pngs, vec![3, 3], "both files in year/month folders");
+}
+
+#[test]
+fn dry_run_does_not_bind_the_library() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"image a");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .success();
+    assert!(!target.path().join(".library.toml").exists());
+}
+
+#[test]
+fn migrate_moves_files_and_updates_references() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"image a");
+    create_file(source.path(), "b.png", b"image a");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--execute"])
+        .assert()
+        .success();
+
+    let migrate = |execute: bool| {
+        let mut command = cmd();
+        command.args(["library", "migrate", target.path().to_str().unwrap(), "--naming=IMG_{year}{month}{day}_{hour}{minute}{second}"]);
+        if execute {
+            command.arg("--execute");
+        }
+        command.assert()
+    };
+    migrate(false)
+        .success()
+        .stdout(predicate::str::contains("[DRY RUN] 1 files moved, 0 left in place"));
+    assert!(!fs::read_to_string(target.path().join(".library.toml")).unwrap().contains("IMG_"));
+
+    migrate(true).success().stdout(predicate::str::contains("1 files moved"));
+
+    let month_dir = walkdir::WalkDir::new(target.path())
+        .into_iter()
+        .map(|e| e.unwrap())
+        .find(|e| e.depth() == 2 && e.file_type().is_dir() && !e.path().starts_with(target.path().join(".imports")))
+        .expect("month directory")
+        .into_path();
+    let manifest = read_manifest(&month_dir);
+    let (name, _) = manifest["files"].as_object().unwrap().iter().next().unwrap();
+    assert!(name.starts_with("IMG_"), "{}", name);
+    assert!(month_dir.join(name).exists());
+
+    let duplicates = read_manifest(&target.path().join("duplicates"));
+    let duplicate = duplicates["files"].as_object().unwrap().values().next().unwrap();
+    assert!(duplicate["duplicate_of"].as_str().unwrap().ends_with(name.as_str()));
+
+    // Later imports use the new naming and still find the moved file
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("0 imported, 2 duplicates"));
+}
+
+#[test]
+fn failed_migration_keeps_finished_folders_and_restores_the_rest() {
+    let target = TempDir::new().unwrap();
+    let t = target.path();
+    let entry = |dir: &str, name: &str, duplicate_of: Option<&str>| {
+        create_file(&t.join(dir), name, format!("content of {}", name).as_bytes());
+        let mut entry = serde_json::json!({
+            "hash": sha256_hex(&t.join(dir).join(name)),
+            "hash_algorithm": "sha256",
+            "original_path": format!("/camera/{}", name),
+            "original_name": name,
+            "imported_at": "2024-01-01T00:00:00Z",
+            "file_size_bytes": 20,
+        });
+        if let Some(canonical) = duplicate_of {
+            entry["duplicate_of"] = canonical.into();
+            fs::remove_file(t.join(dir).join(name)).unwrap();
+        }
+        let manifest_path = t.join(dir).join(".manifest.json");
+        let mut manifest = fs::read_to_string(&manifest_path)
+            .map(|m| serde_json::from_str(&m).unwrap())
+            .unwrap_or_else(|_| serde_json::json!({ "version": 2, "files": {} }));
+        manifest["files"][name] = entry;
+        fs::write(manifest_path, manifest.to_string()).unwrap();
+    };
+    let empty_files = |t: &Path| -> Vec<std::path::PathBuf> {
+        walkdir::WalkDir::new(t)
+            .into_iter()
+            .flatten()
+            .filter(|e| e.file_type().is_file() && !e.file_name().to_string_lossy().starts_with('.'))
+            .filter(|e| e.metadata().unwrap().len() == 0)
+            .map(|e| e.into_path())
+            .collect()
+    };
+    entry("2019/03", "20190301_120000.png", None);
+    entry("2020/05", "20200502_080000.png", None);
+    entry("duplicates", "copy.png", Some("2020/05/20200502_080000.png"));
+    fs::write(
+        t.join(".library.toml"),
+        "version = 1\nlayout = \"{year}/{month}\"\nnaming = \"{year}{month}{day}_{hour}{minute}{second}\"\n",
+    )
+    .unwrap();
+    // Saving the 2020 manifest fails once the 2020/05 file has moved
+    fs::create_dir_all(t.join("2020/.manifest.json.tmp")).unwrap();
+
+    let migrate = || {
+        cmd()
+            .args(["library", "migrate", t.to_str().unwrap(), "--layout={year}", "--execute"])
+            .assert()
+    };
+    migrate().code(1).stderr(predicate::str::contains("Migration of"));
+
+    assert!(t.join("2019/20190301_120000.png").exists(), "finished folder keeps the new layout");
+    assert!(read_manifest(&t.join("2019"))["files"]["20190301_120000.png"].is_object());
+    assert!(!t.join("2019/03").exists());
+    assert!(t.join("2020/05/20200502_080000.png").exists(), "failed folder is put back");
+    assert!(!t.join("2020/20200502_080000.png").exists());
+    assert!(read_manifest(&t.join("2020/05"))["files"]["20200502_080000.png"].is_object());
+    assert!(!t.join("2020/.manifest.json").exists());
+    let duplicate_of = |t: &Path| read_manifest(&t.join("duplicates"))["files"]["copy.png"]["duplicate_of"].clone();
+    assert_eq!(duplicate_of(t), "2020/05/20200502_080000.png");
+    assert!(fs::read_to_string(t.join(".library.toml")).unwrap().contains("{year}/{month}"));
+    assert_eq!(empty_files(t), Vec::<std::path::PathBuf>::new(), "no placeholder may be left behind");
+
+    // Once the cause is fixed, a second run finishes the migration
+    fs::remove_dir(t.join("2020/.manifest.json.tmp")).unwrap();
+    migrate().success().stdout(predicate::str::contains("1 files moved, 0 left in place"));
+    assert!(t.join("2020/20200502_080000.png").exists());
+    assert!(!t.join("2020/05").exists());
+    assert_eq!(duplicate_of(t), "2020/20200502_080000.png");
+    assert!(fs::read_to_string(t.join(".library.toml")).unwrap().contains("layout = \"{year}\""));
+
+    // A folder that cannot be created fails the migration after another
+    // file in the same folder was given its new name
+    entry("2021", "20210601_120000.png", None);
+    entry("2021", "20210602_120000.png", None);
+    fs::write(t.join("2021/0602"), b"in the way").unwrap();
+    cmd()
+        .args(["library", "migrate", t.to_str().unwrap(), "--layout={year}/{month}{day}", "--execute"])
+        .assert()
+        .code(1)
+        .stderr(predicate::str::contains("Migration of"));
+    assert!(t.join("2021/20210601_120000.png").exists());
+    assert!(!t.join("2021/0601").exists(), "folders created for the failed folder are removed");
+    assert_eq!(empty_files(t), Vec::<std::path::PathBuf>::new(), "no placeholder may be left behind");
+}
+
+// --- Ignore Patterns ---
+
+#[test]
+fn system_clutter_is_ignored() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "IMG_1234.jpg", MINIMAL_JPEG);
+    create_file(source.path(), "._IMG_1234.jpg", b"AppleDouble resource fork");
+    create_file(source.path(), "__MACOSX/._IMG_1234.jpg", b"AppleDouble resource fork");
+    create_file(source.path(), ".Trashes/501/old.png", b"trashed image");
+    create_file(source.path(), ".Spotlight-V100/store.db", b"index");
+    create_file(source.path(), "Thumbs.db", b"thumbnail cache");
+    create_file(source.path(), ".thumbnails/normal/abc.png", b"thumbnail");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap(), "--output=jsonl"])
+        .assert()
+        .success()
+        .stdout(predicate::str::contains("\"imported\":1,\"duplicates\":0,\"corrupt\":0,\"undated\":0,\"skipped\":0"))
+        .stdout(predicate::str::contains("._IMG_1234").not());
+}
+
+#[test]
+fn organiserignore_and_exclude_leave_out_files() {
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+
+    create_file(source.path(), "keep.png", b"keep me");
+    create_file(source.path(), "exports/web.png", b"resized copy");
+    create_file(source.path(), "drafts/draft.png", b"draft");
+    create_file(source.path(), "scratch.png", b"scratch");
+    create_file(source.path(), ".organiserignore", b"# generated files\nexports/\ndrafts/\n");
+
+    cmd()
+        .args(["import", source.path().to_str().unwrap(), target.path().to_str().unwrap()])
+        .args(["--exclude", "scratch.png", "--exclude", "!drafts/"])
+        .assert()
+        .success()
+        .stderr(predicate::str::contains("keep.png"))
+        .stderr(predicate::str::contains("draft.png"))
+        .stderr(predicate::str::contains("web.png").not())
+        .stderr(predicate::str::contains("scratch.png").not())
+        .stderr(predicate::str::contains(".organiserignore").not())
+        .stdout(predicate::str::contains("2 imported, 0 duplicates, 0 corrupt, 0 undated, 0 skipped"));
+}
+
+// --- Extension Coverage ---
+
+#[test]
+fn all_30_spec_extensions_are_recognized() {
+    let extensions = [
+        "heic", "heif", "jpeg", "jpg", "png", "tiff", "tif", "webp", "bmp", "gif", "avif",
+        "cr2", "cr3", "nef", "arw", "raf", "rw2", "dng", "orf", "pef", "srw", "3fr",
+        "mov", "mp4", "m4v", "avi", "mkv", "3gp",
+        "aae",
+    ];
+
+    for ext in &extensions {
+        let path = std::path::PathBuf::from(format!("test.{}", ext));
+        match image_organiser::scan::classify_file(&path) {
+            image_organiser::scan::MediaFile::Recognized { .. } => {}
+            image_organiser::scan::MediaFile::Unrecognized { .. } => {
+                panic!(".{} must be recognized per spec", ext);
+            }
+        }
+    }
+}
+
+#[test]
+fn case_insensitive_extensions() {
+    let cases = ["JPG", "Heic", "MOV", "Png", "AAE"];
+    for ext in &cases {
+        let path = std::path::PathBuf::from(format!("test.{}", ext));
+        match image_organiser::scan::classify_file(&path) {
+            image_organiser::scan::MediaFile::Recognized { .. } => {}
+            image_organiser::scan::MediaFile::Unrecognized { .. } => {
+                panic!(".{} must be recognized (case insensitive)", ext);
+            }
+        }
+    }
+}
+
+// --- Library API ---
+
+#[derive(Default)]
+struct Recorder {
+    files: std::sync::Mutex<Vec<(String, image_organiser::events::Category)>>,
+    warnings: std::sync::Mutex<Vec<String>>,
+}
+
+impl image_organiser::import::Observer for Recorder {
+    fn file(&self, event: &image_organiser::events::FileEvent) {
+        self.files.lock().unwrap().push((event.source.clone(), event.category));
+    }
+
+    fn warning(&self, message: &str) {
+        self.warnings.lock().unwrap().push(message.to_string());
+    }
+}
+
+#[test]
+fn importer_reports_each_file_to_its_observer() {
+    use image_organiser::events::Category;
+    use image_organiser::import::{DuplicatePolicy, ImportError, Importer};
+
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"image a");
+    create_file(source.path(), "b.png", b"image a");
+    create_file(source.path(), "notes.txt", b"text");
+
+    let recorder = Recorder::default();
+    let outcome = Importer::new(source.path(), target.path())
+        .execute(true)
+        .duplicates(DuplicatePolicy::Skip)
+        .run(&recorder)
+        .unwrap();
+
+    assert_eq!(outcome.summary.imported, 1);
+    assert_eq!(outcome.summary.duplicates, 1);
+    assert_eq!(outcome.summary.skipped, 1);
+    assert!(outcome.stopped.is_none());
+    let source_of = |name: &str| source.path().join(name).display().to_string();
+    assert_eq!(
+        *recorder.files.lock().unwrap(),
+        vec![
+            (source_of("notes.txt"), Category::Skipped),
+            (source_of("a.png"), Category::Imported),
+            (source_of("b.png"), Category::Duplicate),
+        ]
+    );
+    assert_eq!(outcome.files.len(), 3);
+
+    let refused = Importer::new(source.path(), target.path())
+        .duplicates(DuplicatePolicy::DeleteSource)
+        .run(&image_organiser::import::Silent);
+    assert!(matches!(refused, Err(ImportError::DeleteSourceRequiresMove)));
+}
+
+#[test]
+fn corrupt_manifest_and_cache_are_reported_to_the_observer() {
+    use image_organiser::import::Importer;
+
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "a.png", b"image a");
+    create_file(target.path(), "2020/01/.manifest.json", b"{ not json");
+    create_file(target.path(), ".hash-cache.json", b"{ not json");
+
+    let recorder = Recorder::default();
+    let outcome = Importer::new(source.path(), target.path()).execute(true).run(&recorder).unwrap();
+    assert_eq!(outcome.summary.imported, 1);
+    let warnings = recorder.warnings.lock().unwrap();
+    assert!(warnings.iter().any(|w| w.starts_with("Corrupt manifest at") && w.contains("2020")));
+    assert!(warnings.iter().any(|w| w.starts_with("Corrupt hash cache at")));
+}
+
+struct FixedDate;
+
+impl image_organiser::metadata::DateExtractor for FixedDate {
+    fn extract(&self, _input: &image_organiser::metadata::DateInput) -> Option<image_organiser::metadata::DateExtracted> {
+        Some(image_organiser::metadata::DateExtracted::Found {
+            year: 2001,
+            month: 2,
+            day: 3,
+            hour: 4,
+            minute: 5,
+            second: 6,
+            source: image_organiser::metadata::DateSource::Other("fixed"),
+        })
+    }
+}
+
+#[test]
+fn importer_records_the_date_extractor_that_won() {
+    use image_organiser::metadata::{BuiltinExtractor, DateChain, DateExtractor};
+    use std::sync::Arc;
+
+    let source = TempDir::new().unwrap();
+    let target = TempDir::new().unwrap();
+    create_file(source.path(), "photo.png", b"image a");
+
+    let chain = DateChain::new(vec![
+        Arc::new(BuiltinExtractor::Exif) as Arc<dyn DateExtractor>,
+        Arc::new(FixedDate),
+        Arc::new(BuiltinExtractor::Filesystem),
+    ]);
+    let outcome = image_organiser::import::Importer::new(source.path(), target.path())
+        .execute(true)
+        .date_chain(chain)
+        .run(&image_organiser::import::Silent)
+        .unwrap();
+
+    assert_eq!(outcome.files[0].date_source.as_deref(), Some("fixed"));
+    assert_eq!(outcome.files[0].date.as_deref(), Some("2001-02-03T04:05:06"));
+    let manifest = read_manifest(&target.path().join("2001/02"));
+    assert_eq!(manifest["files"]["20010203_040506.png"]["date_source"], "fixed");
 }